@@ -1,5 +1,5 @@
 use crate::aabb::Aabb;
-use crate::hit_checker::{HitRecord, Hittable, HittableList};
+use crate::hit_checker::{HitRecord, Hittable, HittableList, collect_emitter};
 use crate::interval::Interval;
 use crate::ray::Ray;
 use std::cmp::Ordering;
@@ -33,6 +33,11 @@ impl BvhNode {
 
         let object_span = end - start;
         match object_span {
+            //空列表得到一个什么也不会击中的节点
+            0 => {
+                left = Arc::new(HittableList::default());
+                right = left.clone();
+            }
             1 => {
                 left = objects[start].clone();
                 right = objects[start].clone();
@@ -74,6 +79,10 @@ impl Hittable for BvhNode {
             return false;
         }
         let hit_left = self.left.hit(r, ray_t, rec);
+        //只有一个子节点时不再求交第二次，否则带镂空的网格会被按不透明度抽样两次
+        if Arc::ptr_eq(&self.left, &self.right) {
+            return hit_left;
+        }
         let new_max = if hit_left { rec.t } else { ray_t.max };
         let right_t = Interval::new(ray_t.min, new_max);
         let hit_right = self.right.hit(r, right_t, rec);
@@ -83,4 +92,12 @@ impl Hittable for BvhNode {
     fn bounding_box(&self) -> Aabb {
         self.bbox
     }
+
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Hittable>>) {
+        collect_emitter(&self.left, lights);
+        //只有一个子节点时左右是同一个对象
+        if !Arc::ptr_eq(&self.left, &self.right) {
+            collect_emitter(&self.right, lights);
+        }
+    }
 }
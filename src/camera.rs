@@ -1,4 +1,4 @@
-use crate::random::{random_double, random_in_unit_disk};
+use crate::random::{random_2d, random_double, random_in_unit_disk};
 use crate::ray::Ray;
 use crate::vec3::{Point3, Vec3};
 
@@ -10,7 +10,6 @@ pub struct Camera {
     defocus_angle: f64,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
-    recip_sqrt_spp: f64,
 }
 
 impl Camera {
@@ -20,7 +19,6 @@ impl Camera {
         pixel_delta_v: Vec3,
         pixel00_loc: Vec3,
         (defocus_angle, defocus_disk_u, defocus_disk_v): (f64, Vec3, Vec3),
-        recip_sqrt_spp: f64,
     ) -> Self {
         Self {
             ctr: center,
@@ -30,7 +28,6 @@ impl Camera {
             defocus_angle,
             defocus_disk_u,
             defocus_disk_v,
-            recip_sqrt_spp,
         }
     }
 
@@ -39,19 +36,14 @@ impl Camera {
     }
 
     pub fn sample_square(&self) -> Vec3 {
-        //二维随机向量
-        Vec3::new(random_double() - 0.5, random_double() - 0.5, 0.0)
+        //二维随机向量，像素内的分层由 sampler 负责
+        let (px, py) = random_2d();
+        Vec3::new(px - 0.5, py - 0.5, 0.0)
     }
 
-    fn sample_square_stratified(&self, s_i: u32, s_j: u32) -> Vec3 {
-        let px = ((s_i as f64 + random_double()) * self.recip_sqrt_spp) - 0.5;
-        let py = ((s_j as f64 + random_double()) * self.recip_sqrt_spp) - 0.5;
-        Vec3::new(px, py, 0.0)
-    }
-
-    pub fn get_ray(&self, i: u32, j: u32, s_i: u32, s_j: u32) -> Ray {
+    pub fn get_ray(&self, i: u32, j: u32) -> Ray {
         //向指定位置发射一条光线
-        let offset = self.sample_square_stratified(s_i, s_j); //小范围内随机取样
+        let offset = self.sample_square(); //小范围内随机取样
         let pixel_sample = self.pixel00_loc
             + ((i as f64 + offset.x()) * self.pixel_delta_u)
             + ((j as f64 + offset.y()) * self.pixel_delta_v);
@@ -0,0 +1,269 @@
+use crate::vec3color::Color;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &str = "RAYTRACER-CHECKPOINT 4";
+
+//文件头不可信，分配缓冲区之前先检查分辨率，2^28 像素约需 9 GB
+const MAX_PIXELS: u64 = 1 << 28;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(std::io::Error),
+    Format(String),
+    Mismatch(String),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "cannot access the checkpoint: {}", e),
+            CheckpointError::Format(msg) => write!(f, "malformed checkpoint: {}", msg),
+            CheckpointError::Mismatch(msg) => {
+                write!(f, "checkpoint does not match this render: {}", msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CheckpointError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CheckpointError {
+    fn from(e: std::io::Error) -> Self {
+        CheckpointError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveSettings {
+    pub min_spp: u32,
+    pub max_spp: u32,
+    pub threshold: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: i32,
+    pub max_depth: i32,
+    pub seed: u64,
+    pub sampler: String,
+    pub max_diffuse: u32, //u32::MAX 为不限
+    pub max_specular: u32,
+    pub max_volume: u32,
+    pub roulette_depth: Option<u32>,
+    pub scene_hash: u64,                    //场景描述的哈希
+    pub adaptive: Option<AdaptiveSettings>, //None 为固定采样数
+}
+
+//FNV-1a，跨版本和平台稳定，用来识别检查点对应的场景
+pub fn scene_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+//累积的辐射度之和、亮度平方和（用于估计方差）与每个像素已完成的样本数
+pub struct Checkpoint {
+    pub settings: RenderSettings,
+    pub accum: Vec<Color>,
+    pub accum_sq: Vec<f64>,
+    pub counts: Vec<u32>,
+}
+
+impl Checkpoint {
+    //先写临时文件再改名，渲染中途被杀掉也不会留下半个检查点
+    pub fn save(&self, path: &Path) -> Result<(), CheckpointError> {
+        if let Some(prefix) = path.parent() {
+            std::fs::create_dir_all(prefix)?;
+        }
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        {
+            let mut writer = BufWriter::new(File::create(&tmp)?);
+            writeln!(writer, "{}", MAGIC)?;
+            let header = serde_json::to_string(&self.settings)
+                .map_err(|e| CheckpointError::Format(e.to_string()))?;
+            writeln!(writer, "{}", header)?;
+            for color in &self.accum {
+                for c in color.e {
+                    writer.write_all(&c.to_le_bytes())?;
+                }
+            }
+            for sq in &self.accum_sq {
+                writer.write_all(&sq.to_le_bytes())?;
+            }
+            for count in &self.counts {
+                writer.write_all(&count.to_le_bytes())?;
+            }
+            writer.flush()?;
+        }
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    //只读取文件头中的渲染设置
+    pub fn load_settings(path: &Path) -> Result<RenderSettings, CheckpointError> {
+        read_header(&mut BufReader::new(File::open(path)?), path)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, CheckpointError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let settings = read_header(&mut reader, path)?;
+
+        let pixels = settings.width as usize * settings.height as usize;
+        let mut accum = Vec::with_capacity(pixels);
+        let mut f64_buf = [0u8; 8];
+        for _ in 0..pixels {
+            let mut color = Color::default();
+            for i in 0..3 {
+                reader.read_exact(&mut f64_buf).map_err(truncated)?;
+                color[i] = f64::from_le_bytes(f64_buf);
+            }
+            accum.push(color);
+        }
+        let mut accum_sq = Vec::with_capacity(pixels);
+        for _ in 0..pixels {
+            reader.read_exact(&mut f64_buf).map_err(truncated)?;
+            accum_sq.push(f64::from_le_bytes(f64_buf));
+        }
+        let mut counts = Vec::with_capacity(pixels);
+        let mut u32_buf = [0u8; 4];
+        for _ in 0..pixels {
+            reader.read_exact(&mut u32_buf).map_err(truncated)?;
+            counts.push(u32::from_le_bytes(u32_buf));
+        }
+
+        Ok(Self {
+            settings,
+            accum,
+            accum_sq,
+            counts,
+        })
+    }
+
+    //除采样数外的设置都必须一致；采样数可以调大，用来给旧的渲染追加样本
+    pub fn check_compatible(&self, settings: &RenderSettings) -> Result<(), CheckpointError> {
+        let saved = &self.settings;
+        if saved.scene_hash != settings.scene_hash {
+            return Err(CheckpointError::Mismatch(
+                "it was rendered from a different scene".to_string(),
+            ));
+        }
+        if (saved.width, saved.height) != (settings.width, settings.height) {
+            return Err(CheckpointError::Mismatch(format!(
+                "resolution is {}x{}, expected {}x{}",
+                saved.width, saved.height, settings.width, settings.height
+            )));
+        }
+        if saved.max_depth != settings.max_depth {
+            return Err(CheckpointError::Mismatch(format!(
+                "max depth is {}, expected {}",
+                saved.max_depth, settings.max_depth
+            )));
+        }
+        if saved.seed != settings.seed {
+            return Err(CheckpointError::Mismatch(format!(
+                "seed is {}, expected {}",
+                saved.seed, settings.seed
+            )));
+        }
+        if saved.sampler != settings.sampler {
+            return Err(CheckpointError::Mismatch(format!(
+                "sampler is '{}', expected '{}'",
+                saved.sampler, settings.sampler
+            )));
+        }
+        let limits = |s: &RenderSettings| {
+            [s.max_diffuse, s.max_specular, s.max_volume].map(|limit| {
+                if limit == u32::MAX {
+                    "unlimited".to_string()
+                } else {
+                    limit.to_string()
+                }
+            })
+        };
+        let (a, b) = (limits(saved), limits(settings));
+        if a != b {
+            return Err(CheckpointError::Mismatch(format!(
+                "diffuse, specular and volume bounce limits are {}, {}, {}, expected {}, {}, {}",
+                a[0], a[1], a[2], b[0], b[1], b[2]
+            )));
+        }
+        if saved.roulette_depth != settings.roulette_depth {
+            let describe = |depth: Option<u32>| match depth {
+                Some(depth) => format!("from bounce {}", depth),
+                None => "off".to_string(),
+            };
+            return Err(CheckpointError::Mismatch(format!(
+                "russian roulette is {}, expected {}",
+                describe(saved.roulette_depth),
+                describe(settings.roulette_depth)
+            )));
+        }
+        match (&saved.adaptive, &settings.adaptive) {
+            (None, None) => {}
+            (Some(_), None) => {
+                return Err(CheckpointError::Mismatch(
+                    "it was rendered with adaptive sampling".to_string(),
+                ));
+            }
+            (None, Some(_)) => {
+                return Err(CheckpointError::Mismatch(
+                    "it was rendered without adaptive sampling".to_string(),
+                ));
+            }
+            (Some(a), Some(b)) if a != b => {
+                return Err(CheckpointError::Mismatch(format!(
+                    "adaptive sampling uses min spp {}, max spp {}, threshold {}, expected {}, {}, {}",
+                    a.min_spp, a.max_spp, a.threshold, b.min_spp, b.max_spp, b.threshold
+                )));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn read_header(reader: &mut impl BufRead, path: &Path) -> Result<RenderSettings, CheckpointError> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim_end() != MAGIC {
+        return Err(CheckpointError::Format(format!(
+            "'{}' is not a checkpoint file of this version",
+            path.display()
+        )));
+    }
+    line.clear();
+    reader.read_line(&mut line)?;
+    let settings: RenderSettings =
+        serde_json::from_str(&line).map_err(|e| CheckpointError::Format(e.to_string()))?;
+    let pixels = settings.width as u64 * settings.height as u64;
+    if pixels == 0 || pixels > MAX_PIXELS {
+        return Err(CheckpointError::Format(format!(
+            "resolution {}x{} is out of range",
+            settings.width, settings.height
+        )));
+    }
+    Ok(settings)
+}
+
+fn truncated(e: std::io::Error) -> CheckpointError {
+    if e.kind() == std::io::ErrorKind::UnexpectedEof {
+        CheckpointError::Format("file is truncated".to_string())
+    } else {
+        CheckpointError::Io(e)
+    }
+}
@@ -0,0 +1,285 @@
+use crate::hit_checker::HitRecord;
+use crate::material::{Material, ScatterRecord};
+use crate::microfacet::{MicrofacetDielectric, TrowbridgeReitz, reflect, schlick_weight};
+use crate::onb::ONB;
+use crate::pdf::Pdf;
+use crate::random::{random_2d, random_cosine_direction, random_double};
+use crate::ray::Ray;
+use crate::texture::{SolidColor, Texture};
+use crate::tonemap::luminance;
+use crate::vec3::{Vec3, dot, unit_vector};
+use crate::vec3color::Color;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+//sheen 的染色程度，没有作为参数开放
+const SHEEN_TINT: f64 = 0.5;
+
+//标量参数取纹理的 r 通道
+pub struct DisneyParams {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>, //非金属的反射率，0.5 对应 F0 = 0.04
+    pub specular_tint: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_gloss: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub ior: Arc<dyn Texture>,
+}
+
+fn constant(value: f64) -> Arc<dyn Texture> {
+    Arc::new(SolidColor::new(Color::new(value, value, value)))
+}
+
+impl Default for DisneyParams {
+    fn default() -> Self {
+        Self {
+            base_color: constant(0.8),
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            sheen: constant(0.0),
+            clearcoat: constant(0.0),
+            clearcoat_gloss: constant(1.0),
+            transmission: constant(0.0),
+            ior: constant(1.5),
+        }
+    }
+}
+
+//Disney 原理化 BSDF：漫反射（含逆反射和 sheen）、GGX 镜面反射、清漆层，
+//以及 transmission 控制的粗糙玻璃
+pub struct Disney {
+    params: DisneyParams,
+}
+
+impl Disney {
+    pub fn new(params: DisneyParams) -> Self {
+        Self { params }
+    }
+
+    fn bsdf(&self, r_in: &Ray, rec: &HitRecord) -> DisneyBsdf {
+        let (u, v, p) = (rec.u, rec.v, &rec.pos);
+        let scalar = |tex: &Arc<dyn Texture>| tex.value(u, v, p).x();
+        let base_color = self.params.base_color.value(u, v, p);
+        let metallic = scalar(&self.params.metallic).clamp(0.0, 1.0);
+        let roughness = scalar(&self.params.roughness).clamp(0.0, 1.0);
+        let specular = scalar(&self.params.specular).max(0.0);
+        let specular_tint = scalar(&self.params.specular_tint).clamp(0.0, 1.0);
+        let clearcoat_gloss = scalar(&self.params.clearcoat_gloss).clamp(0.0, 1.0);
+        let transmission = scalar(&self.params.transmission).clamp(0.0, 1.0);
+        let ior = scalar(&self.params.ior).max(1e-3);
+
+        //去掉亮度、只保留色调的底色
+        let white = Color::new(1.0, 1.0, 1.0);
+        let lum = luminance(&base_color);
+        let tint = if lum > 0.0 { base_color / lum } else { white };
+        let dielectric_spec0 =
+            0.08 * specular * ((1.0 - specular_tint) * white + specular_tint * tint);
+
+        let distribution =
+            TrowbridgeReitz::isotropic(TrowbridgeReitz::roughness_to_alpha(roughness));
+        let frame = ONB::new(&rec.normal);
+        let wo = frame.to_local(-unit_vector(r_in.direction()));
+        DisneyBsdf {
+            frame,
+            wo,
+            base_color,
+            roughness,
+            spec0: (1.0 - metallic) * dielectric_spec0 + metallic * base_color,
+            sheen: scalar(&self.params.sheen).max(0.0)
+                * ((1.0 - SHEEN_TINT) * white + SHEEN_TINT * tint),
+            clearcoat: scalar(&self.params.clearcoat).max(0.0),
+            clearcoat_alpha: 0.1 * (1.0 - clearcoat_gloss) + 0.001 * clearcoat_gloss,
+            diffuse_weight: (1.0 - metallic) * (1.0 - transmission),
+            glass_weight: (1.0 - metallic) * transmission,
+            distribution,
+            glass: MicrofacetDielectric::new(
+                distribution,
+                if rec.front_face { ior } else { 1.0 / ior },
+            ),
+        }
+    }
+}
+
+impl Material for Disney {
+    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, s_rec: &mut ScatterRecord) -> bool {
+        if let Some(n) =
+            self.params
+                .base_color
+                .normal(rec.u, rec.v, rec.normal, rec.tangent, rec.bitangent)
+        {
+            rec.normal = n;
+        }
+        s_rec.attenuation = Color::new(1.0, 1.0, 1.0);
+        s_rec.pdf_ptr = Arc::new(self.bsdf(r_in, rec));
+        s_rec.skip_pdf = false;
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, _s_rec: &ScatterRecord, scattered: &Ray) -> Color {
+        let bsdf = self.bsdf(r_in, rec);
+        bsdf.f(bsdf.frame.to_local(unit_vector(scattered.direction())))
+    }
+
+    fn alpha(&self, u: f64, v: f64) -> f64 {
+        self.params.base_color.alpha(u, v).unwrap_or(1.0)
+    }
+}
+
+//某个交点上求值后的参数，同时作为按 BSDF 采样方向的 pdf
+struct DisneyBsdf {
+    frame: ONB,
+    wo: Vec3, //着色坐标系中指向观察者，法线朝向 wo 一侧
+    base_color: Color,
+    roughness: f64,
+    spec0: Color,
+    sheen: Color,
+    clearcoat: f64,
+    clearcoat_alpha: f64,
+    diffuse_weight: f64,
+    glass_weight: f64,
+    distribution: TrowbridgeReitz,
+    glass: MicrofacetDielectric,
+}
+
+impl DisneyBsdf {
+    //依次为漫反射、镜面反射、清漆、玻璃四个分量被选中的概率
+    fn lobe_probabilities(&self) -> [f64; 4] {
+        let fresnel = schlick_weight(self.wo.z());
+        let specular = self.spec0 + (Color::new(1.0, 1.0, 1.0) - self.spec0) * fresnel;
+        let weights = [
+            self.diffuse_weight * luminance(&self.base_color),
+            (1.0 - self.glass_weight) * luminance(&specular),
+            0.25 * self.clearcoat,
+            self.glass_weight,
+        ];
+        let total: f64 = weights.iter().sum();
+        if total > 0.0 {
+            weights.map(|w| w / total)
+        } else {
+            [1.0, 0.0, 0.0, 0.0]
+        }
+    }
+
+    //BSDF 乘以 |cosθi|
+    fn f(&self, wi: Vec3) -> Color {
+        let wo = self.wo;
+        let (cos_o, cos_i) = (wo.z(), wi.z());
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        if cos_i < 0.0 {
+            return self.glass_weight * self.glass.f(wo, wi) * self.base_color;
+        }
+
+        let wh = wo + wi;
+        if wh.near_zero() {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let wh = unit_vector(&wh);
+        let cos_d = dot(&wi, &wh);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let mut f = Color::new(0.0, 0.0, 0.0);
+
+        if self.diffuse_weight > 0.0 {
+            let (fo, fi) = (schlick_weight(cos_o), schlick_weight(cos_i));
+            let rr = 2.0 * self.roughness * cos_d * cos_d;
+            let diffuse = self.base_color / PI
+                * ((1.0 - fo / 2.0) * (1.0 - fi / 2.0) + rr * (fo + fi + fo * fi * (rr - 1.0)));
+            let sheen = self.sheen * schlick_weight(cos_d);
+            f += self.diffuse_weight * (diffuse + sheen);
+        }
+
+        let microfacet =
+            self.distribution.d(wh) * self.distribution.g(wo, wi) / (4.0 * cos_o * cos_i);
+        if self.glass_weight < 1.0 {
+            let fresnel = self.spec0 + (white - self.spec0) * schlick_weight(cos_d);
+            f += (1.0 - self.glass_weight) * microfacet * fresnel;
+        }
+        if self.clearcoat > 0.0 {
+            let coat = TrowbridgeReitz::isotropic(0.25);
+            let fresnel = 0.04 + 0.96 * schlick_weight(cos_d);
+            f += 0.25
+                * self.clearcoat
+                * gtr1(wh.z(), self.clearcoat_alpha)
+                * fresnel
+                * coat.g(wo, wi)
+                / (4.0 * cos_o * cos_i)
+                * white;
+        }
+        f * cos_i + self.glass_weight * self.glass.f(wo, wi) * white
+    }
+
+    fn sample(&self) -> Vec3 {
+        let wo = self.wo;
+        let [diffuse, specular, clearcoat, _] = self.lobe_probabilities();
+        let u = random_double();
+        if u < diffuse {
+            random_cosine_direction()
+        } else if u < diffuse + specular {
+            reflect(wo, self.distribution.sample_wm(wo, random_2d()))
+        } else if u < diffuse + specular + clearcoat {
+            reflect(wo, sample_gtr1(self.clearcoat_alpha, random_2d()))
+        } else {
+            self.glass.sample(wo, random_double(), random_2d())
+        }
+    }
+
+    //生成 wi 的概率密度，和 MicrofacetDielectric::pdf 一样按实际的生成方式累加
+    fn pdf(&self, wi: Vec3) -> f64 {
+        let wo = self.wo;
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        let [diffuse, specular, clearcoat, glass] = self.lobe_probabilities();
+        let mut pdf = 0.0;
+        if wi.z() > 0.0 {
+            pdf += diffuse * wi.z() / PI;
+        }
+
+        let wh = wo + wi;
+        if !wh.near_zero() {
+            let wh = unit_vector(&wh);
+            let dot_o = dot(&wo, &wh);
+            //反射时 dwh/dwi = 1 / (4 |wo·wh|)
+            pdf += specular * self.distribution.pdf(wo, wh) / (4.0 * dot_o)
+                + clearcoat * gtr1(wh.z(), self.clearcoat_alpha) * wh.z() / (4.0 * dot_o);
+        }
+        if glass > 0.0 {
+            pdf += glass * self.glass.pdf(wo, wi);
+        }
+        pdf
+    }
+}
+
+impl Pdf for DisneyBsdf {
+    fn value(&self, direction: Vec3) -> f64 {
+        self.pdf(self.frame.to_local(unit_vector(&direction)))
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.frame.transform(self.sample())
+    }
+}
+
+//清漆层使用的 GTR1 (Berry) 分布
+fn gtr1(cos_theta: f64, alpha: f64) -> f64 {
+    if cos_theta <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let t = 1.0 + (a2 - 1.0) * cos_theta * cos_theta;
+    (a2 - 1.0) / (PI * a2.ln() * t)
+}
+
+fn sample_gtr1(alpha: f64, (u1, u2): (f64, f64)) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos_theta = ((1.0 - a2.powf(1.0 - u1)) / (1.0 - a2)).max(0.0).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
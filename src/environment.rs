@@ -0,0 +1,346 @@
+use crate::hit_checker::degrees_to_radians;
+use crate::random::random_2d;
+use crate::tonemap::{TransferCurve, luminance};
+use crate::vec3::{Vec3, unit_vector};
+use crate::vec3color::Color;
+use image::{ColorType, ImageError, ImageReader};
+use std::f64::consts::PI;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+//光线射向无穷远处时看到的辐射度
+pub trait Environment: Send + Sync {
+    fn radiance(&self, direction: Vec3) -> Color;
+
+    //能按亮度采样方向的背景才会加入光源列表
+    fn importance_sampled(&self) -> bool {
+        false
+    }
+
+    fn random(&self) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    fn pdf_value(&self, _direction: Vec3) -> f64 {
+        0.0
+    }
+
+    //按立体角平均的辐射度，用于估计功率
+    fn average_radiance(&self) -> Color;
+}
+
+pub struct ConstantBackground {
+    color: Color,
+}
+
+impl ConstantBackground {
+    pub fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Environment for ConstantBackground {
+    fn radiance(&self, _direction: Vec3) -> Color {
+        self.color
+    }
+
+    fn average_radiance(&self) -> Color {
+        self.color
+    }
+}
+
+#[derive(Debug)]
+pub enum EnvironmentError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Decode {
+        path: PathBuf,
+        source: ImageError,
+    },
+}
+
+impl fmt::Display for EnvironmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvironmentError::Io { path, source } => {
+                write!(
+                    f,
+                    "cannot open environment map '{}': {}",
+                    path.display(),
+                    source
+                )
+            }
+            EnvironmentError::Decode { path, source } => {
+                write!(
+                    f,
+                    "cannot decode environment map '{}': {}",
+                    path.display(),
+                    source
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for EnvironmentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EnvironmentError::Io { source, .. } => Some(source),
+            EnvironmentError::Decode { source, .. } => Some(source),
+        }
+    }
+}
+
+//等距柱状投影的环境贴图，图片顶行对应 +y，按亮度重要性采样
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<[f32; 3]>, //线性辐射度，未乘 intensity
+    intensity: f64,
+    sin_rotation: f64,
+    cos_rotation: f64,
+    distribution: Distribution2D,
+    average: Color,
+}
+
+impl EnvironmentMap {
+    //rotation 为绕 y 轴旋转的角度；LDR 图片按输出默认的 gamma 2 解码，和图片纹理一致
+    pub fn load(
+        path: impl AsRef<Path>,
+        rotation: f64,
+        intensity: f64,
+    ) -> Result<Self, EnvironmentError> {
+        let path = path.as_ref();
+        let image = ImageReader::open(path)
+            .map_err(|source| EnvironmentError::Io {
+                path: path.to_path_buf(),
+                source,
+            })?
+            .decode()
+            .map_err(|source| EnvironmentError::Decode {
+                path: path.to_path_buf(),
+                source,
+            })?;
+        let hdr = matches!(image.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+        let image = image.to_rgb32f();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let decode = |c: f32| TransferCurve::Gamma2.decode(c as f64) as f32;
+        let pixels = image
+            .pixels()
+            .map(|p| {
+                if hdr {
+                    p.0.map(|c| c.max(0.0))
+                } else {
+                    p.0.map(decode)
+                }
+            })
+            .collect();
+        Ok(Self::new(width, height, pixels, rotation, intensity))
+    }
+
+    pub fn new(
+        width: usize,
+        height: usize,
+        pixels: Vec<[f32; 3]>,
+        rotation: f64,
+        intensity: f64,
+    ) -> Self {
+        let radians = degrees_to_radians(rotation);
+        //每个像素的权重为亮度乘 sinθ，抵消两极附近像素对应的立体角更小
+        let mut weights = Vec::with_capacity(width * height);
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        let mut sin_sum = 0.0;
+        for j in 0..height {
+            let sin_theta = (PI * (j as f64 + 0.5) / height as f64).sin();
+            for i in 0..width {
+                let color = to_color(pixels[j * width + i]);
+                weights.push(luminance(&color) * sin_theta);
+                sum += color * sin_theta;
+                sin_sum += sin_theta;
+            }
+        }
+        let average = if sin_sum > 0.0 {
+            sum / sin_sum * intensity
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        };
+        Self {
+            width,
+            height,
+            pixels,
+            intensity,
+            sin_rotation: radians.sin(),
+            cos_rotation: radians.cos(),
+            distribution: Distribution2D::new(&weights, width.max(1), height),
+            average,
+        }
+    }
+
+    fn pixel(&self, i: usize, j: usize) -> Color {
+        to_color(self.pixels[j * self.width + i])
+    }
+
+    //世界空间方向 -> 贴图坐标 (u, v)，v = 0 为正上方
+    fn direction_to_uv(&self, direction: Vec3) -> (f64, f64) {
+        let d = unit_vector(&Vec3::new(
+            self.cos_rotation * direction.x() - self.sin_rotation * direction.z(),
+            direction.y(),
+            self.sin_rotation * direction.x() + self.cos_rotation * direction.z(),
+        ));
+        let theta = d.y().clamp(-1.0, 1.0).acos();
+        let phi = (-d.z()).atan2(d.x()) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3 {
+        let theta = v * PI;
+        let phi = u * 2.0 * PI;
+        let (x, y, z) = (
+            -theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        );
+        Vec3::new(
+            self.cos_rotation * x + self.sin_rotation * z,
+            y,
+            -self.sin_rotation * x + self.cos_rotation * z,
+        )
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, direction: Vec3) -> Color {
+        if self.width == 0 || self.height == 0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let (u, v) = self.direction_to_uv(direction);
+        //双线性插值，水平方向首尾相接
+        let x = u * self.width as f64 - 0.5;
+        let y = (v * self.height as f64 - 0.5).clamp(0.0, (self.height - 1) as f64);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (sx, sy) = (x - x0, y - y0);
+        let i0 = (x0 as isize).rem_euclid(self.width as isize) as usize;
+        let i1 = (i0 + 1) % self.width;
+        let j0 = y0 as usize;
+        let j1 = (j0 + 1).min(self.height - 1);
+        let color = (1.0 - sx) * (1.0 - sy) * self.pixel(i0, j0)
+            + sx * (1.0 - sy) * self.pixel(i1, j0)
+            + (1.0 - sx) * sy * self.pixel(i0, j1)
+            + sx * sy * self.pixel(i1, j1);
+        color * self.intensity
+    }
+
+    fn importance_sampled(&self) -> bool {
+        self.width > 0 && self.height > 0 && self.intensity > 0.0
+    }
+
+    fn random(&self) -> Vec3 {
+        let (u, v) = self.distribution.sample(random_2d());
+        self.uv_to_direction(u, v)
+    }
+
+    fn pdf_value(&self, direction: Vec3) -> f64 {
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        //(u, v) 上的密度换算到立体角：dω = 2π² sinθ du dv
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn average_radiance(&self) -> Color {
+        self.average
+    }
+}
+
+fn to_color(p: [f32; 3]) -> Color {
+    Color::new(p[0] as f64, p[1] as f64, p[2] as f64)
+}
+
+//[0, 1) 上的分段常数分布，只保存归一化的 CDF
+struct Distribution1D {
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    fn new(func: &[f64]) -> Self {
+        let n = func.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        for (i, f) in func.iter().enumerate() {
+            cdf.push(cdf[i] + f.max(0.0) / n as f64);
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            //全为 0 时退化为均匀分布
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n as f64
+            };
+        }
+        Self { cdf, integral }
+    }
+
+    fn count(&self) -> usize {
+        self.cdf.len() - 1
+    }
+
+    //返回样本位置和所在的段
+    fn sample(&self, u: f64) -> (f64, usize) {
+        let n = self.count();
+        let offset = self.cdf.partition_point(|&c| c <= u).clamp(1, n) - 1;
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0.0 {
+            (u - self.cdf[offset]) / width
+        } else {
+            0.0
+        };
+        (((offset as f64 + du) / n as f64).min(1.0), offset)
+    }
+
+    fn pdf(&self, x: f64) -> f64 {
+        let n = self.count();
+        let offset = ((x * n as f64) as usize).min(n - 1);
+        (self.cdf[offset + 1] - self.cdf[offset]) * n as f64
+    }
+}
+
+//先按行的边缘分布选 v，再按该行的条件分布选 u
+struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    fn new(func: &[f64], width: usize, height: usize) -> Self {
+        let conditional: Vec<Distribution1D> = (0..height.max(1))
+            .map(|j| match func.get(j * width..(j + 1) * width) {
+                Some(row) => Distribution1D::new(row),
+                None => Distribution1D::new(&vec![0.0; width]),
+            })
+            .collect();
+        let integrals: Vec<f64> = conditional.iter().map(|d| d.integral).collect();
+        let marginal = Distribution1D::new(&integrals);
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    fn sample(&self, (u1, u2): (f64, f64)) -> (f64, f64) {
+        let (v, row) = self.marginal.sample(u2);
+        let (u, _) = self.conditional[row].sample(u1);
+        (u, v)
+    }
+
+    fn pdf(&self, u: f64, v: f64) -> f64 {
+        let rows = self.marginal.count();
+        let row = ((v * rows as f64) as usize).min(rows - 1);
+        self.conditional[row].pdf(u) * self.marginal.pdf(v)
+    }
+}
@@ -83,6 +83,23 @@ pub trait Hittable: Send + Sync {
     fn random(&self, _origin: Vec3) -> Vec3 {
         Vec3::new(1.0, 0.0, 0.0)
     }
+
+    //发光图元的功率估计（亮度），不发光的物体和容器为 0
+    fn power(&self) -> f64 {
+        0.0
+    }
+
+    //容器把其中所有发光的图元（连同外层变换）加入 lights
+    fn collect_emitters(&self, _lights: &mut Vec<Arc<dyn Hittable>>) {}
+}
+
+//power() 为正的对象本身就是一个光源，否则继续向下查找
+pub fn collect_emitter(object: &Arc<dyn Hittable>, lights: &mut Vec<Arc<dyn Hittable>>) {
+    if object.power() > 0.0 {
+        lights.push(object.clone());
+    } else {
+        object.collect_emitters(lights);
+    }
 }
 
 #[derive(Default)]
@@ -135,4 +152,10 @@ impl Hittable for HittableList {
         let index = random_int_range(0, int_size - 1) as usize;
         self.objects[index].random(origin)
     }
+
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Hittable>>) {
+        for object in &self.objects {
+            collect_emitter(object, lights);
+        }
+    }
 }
@@ -1,21 +1,34 @@
 pub mod aabb;
 pub mod bvh;
 pub mod camera;
+pub mod checkpoint;
+pub mod disney;
+pub mod environment;
 pub mod hit_checker;
 pub mod interval;
+pub mod light;
 pub mod material;
+pub mod mesh;
+pub mod microfacet;
 pub mod modeling;
 pub mod mtl;
 pub mod my_image;
 pub mod obj;
+pub mod obj_error;
 pub mod onb;
 pub mod pdf;
 pub mod perlin;
+pub mod punctual;
 pub mod random;
 pub mod ray;
 pub mod raytracer;
+pub mod sampler;
+pub mod scene;
 pub mod sketchpad;
+pub mod sky;
 pub mod texture;
+pub mod tile;
+pub mod tonemap;
 pub mod uv;
 pub mod vec3;
 pub mod vec3color;
@@ -0,0 +1,395 @@
+use crate::aabb::Aabb;
+use crate::environment::Environment;
+use crate::hit_checker::Hittable;
+use crate::interval::Interval;
+use crate::punctual::PunctualLight;
+use crate::random::random_double;
+use crate::ray::Ray;
+use crate::tonemap::luminance;
+use crate::vec3::{Point3, Vec3};
+use crate::vec3color::Color;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+enum LightSource {
+    Area(Arc<dyn Hittable>),
+    Environment(Arc<dyn Environment>),
+    Punctual(Arc<dyn PunctualLight>),
+}
+
+pub enum LightSample {
+    //面光源和环境光只给出方向，发光由阴影光线击中的表面决定，和材质采样做 MIS
+    Direction(Vec3),
+    //材质采样击中不到的光源直接给出照度（已除以选择概率），阴影光线只判断遮挡
+    Irradiance {
+        direction: Vec3,
+        distance: f64,
+        irradiance: Color,
+    },
+}
+
+impl LightSample {
+    fn select(self, probability: f64) -> Self {
+        match self {
+            LightSample::Irradiance {
+                direction,
+                distance,
+                irradiance,
+            } => LightSample::Irradiance {
+                direction,
+                distance,
+                irradiance: irradiance / probability,
+            },
+            sample => sample,
+        }
+    }
+}
+
+//参与光源采样的对象，power 决定被选中的概率
+pub struct Light {
+    source: LightSource,
+    power: f64,
+    bbox: Option<Aabb>, //无限远的光源没有包围盒
+}
+
+impl Light {
+    pub fn new(object: Arc<dyn Hittable>, power: f64) -> Self {
+        let bbox = object.bounding_box();
+        Self {
+            source: LightSource::Area(object),
+            power: power.max(0.0),
+            bbox: Some(bbox),
+        }
+    }
+
+    //功率按包住半径为 scene_radius 的场景、亮度相同的球面光源估计
+    pub fn environment(environment: Arc<dyn Environment>, scene_radius: f64) -> Self {
+        let area = 4.0 * PI * scene_radius * scene_radius;
+        let power = PI * area * luminance(&environment.average_radiance());
+        Self {
+            source: LightSource::Environment(environment),
+            power: power.max(0.0),
+            bbox: None,
+        }
+    }
+
+    pub fn punctual(light: Arc<dyn PunctualLight>, scene_radius: f64) -> Self {
+        let power = luminance(&light.power(scene_radius));
+        let bbox = light.position().map(|p| Aabb::from_points(p, p));
+        Self {
+            source: LightSource::Punctual(light),
+            power: power.max(0.0),
+            bbox,
+        }
+    }
+
+    //按图元自身估计的功率
+    pub fn from_emitter(object: Arc<dyn Hittable>) -> Self {
+        let power = object.power();
+        Self::new(object, power)
+    }
+
+    pub fn power(&self) -> f64 {
+        self.power
+    }
+
+    fn sample(&self, origin: Point3) -> LightSample {
+        match &self.source {
+            LightSource::Area(object) => LightSample::Direction(object.random(origin)),
+            LightSource::Environment(environment) => LightSample::Direction(environment.random()),
+            LightSource::Punctual(light) => {
+                let (direction, distance, irradiance) = light.sample(origin);
+                LightSample::Irradiance {
+                    direction,
+                    distance,
+                    irradiance,
+                }
+            }
+        }
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        match &self.source {
+            LightSource::Area(object) => object.pdf_value(origin, direction),
+            LightSource::Environment(environment) => environment.pdf_value(direction),
+            LightSource::Punctual(_) => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightSamplerKind {
+    Uniform,
+    Power,
+    Bvh, //按到着色点的距离和功率逐层选择
+}
+
+impl LightSamplerKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "uniform" => Some(LightSamplerKind::Uniform),
+            "power" => Some(LightSamplerKind::Power),
+            "bvh" => Some(LightSamplerKind::Bvh),
+            _ => None,
+        }
+    }
+}
+
+pub trait LightSampler: Send + Sync {
+    fn is_empty(&self) -> bool;
+
+    //在 origin 处选一个光源并采样
+    fn sample(&self, origin: Point3) -> LightSample;
+
+    //所有光源生成 direction 的立体角密度之和，已乘上各自被选中的概率
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64;
+}
+
+pub fn make_light_sampler(kind: LightSamplerKind, lights: Vec<Light>) -> Arc<dyn LightSampler> {
+    match kind {
+        LightSamplerKind::Uniform => {
+            let weights = vec![1.0; lights.len()];
+            Arc::new(TableLightSampler::new(lights, &weights))
+        }
+        LightSamplerKind::Power => {
+            let weights: Vec<f64> = lights.iter().map(|l| l.power).collect();
+            Arc::new(TableLightSampler::new(lights, &weights))
+        }
+        LightSamplerKind::Bvh => Arc::new(BvhLightSampler::new(lights)),
+    }
+}
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+//按固定权重选择光源，与着色点位置无关
+pub struct TableLightSampler {
+    lights: Vec<Light>,
+    probabilities: Vec<f64>,
+    cdf: Vec<f64>,
+}
+
+impl TableLightSampler {
+    pub fn new(lights: Vec<Light>, weights: &[f64]) -> Self {
+        let total: f64 = weights.iter().sum();
+        //权重全为 0 时退化为均匀选择
+        let probabilities: Vec<f64> = if total > 0.0 {
+            weights.iter().map(|w| w / total).collect()
+        } else {
+            vec![1.0 / lights.len().max(1) as f64; lights.len()]
+        };
+        let cdf = probabilities
+            .iter()
+            .scan(0.0, |sum, p| {
+                *sum += p;
+                Some(*sum)
+            })
+            .collect();
+        Self {
+            lights,
+            probabilities,
+            cdf,
+        }
+    }
+}
+
+impl LightSampler for TableLightSampler {
+    fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    fn sample(&self, origin: Point3) -> LightSample {
+        let u = random_double();
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .min(self.lights.len() - 1);
+        self.lights[index]
+            .sample(origin)
+            .select(self.probabilities[index])
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.lights
+            .iter()
+            .zip(&self.probabilities)
+            .filter(|(_, p)| **p > 0.0)
+            .map(|(light, p)| p * light.pdf_value(origin, direction))
+            .sum()
+    }
+}
+
+enum LightNodeKind {
+    Leaf(usize),
+    Interior(usize, usize),
+}
+
+struct LightNode {
+    bbox: Aabb,
+    power: f64,
+    kind: LightNodeKind,
+}
+
+//光源层次包围盒：每层按两个子树对着色点的重要性随机选一边，
+//离得近、功率大的一簇光源更容易被选中。无限远的光源不进树，和整棵树平分概率
+pub struct BvhLightSampler {
+    lights: Vec<Light>,
+    infinite: Vec<usize>,
+    nodes: Vec<LightNode>, //nodes[0] 为根
+}
+
+impl BvhLightSampler {
+    pub fn new(lights: Vec<Light>) -> Self {
+        let (mut finite, infinite): (Vec<usize>, Vec<usize>) =
+            (0..lights.len()).partition(|&i| lights[i].bbox.is_some());
+        let mut sampler = Self {
+            lights,
+            infinite,
+            nodes: Vec::new(),
+        };
+        if !finite.is_empty() {
+            sampler.build(&mut finite);
+        }
+        sampler
+    }
+
+    //选中某一个无限远光源的概率
+    fn infinite_probability(&self) -> f64 {
+        let choices = self.infinite.len() + usize::from(!self.nodes.is_empty());
+        if choices == 0 {
+            0.0
+        } else {
+            1.0 / choices as f64
+        }
+    }
+
+    fn build(&mut self, indices: &mut [usize]) -> usize {
+        let mut bbox = Aabb::EMPTY;
+        let mut power = 0.0;
+        for &i in indices.iter() {
+            bbox = Aabb::from_box(bbox, self.lights[i].bbox.unwrap_or(Aabb::EMPTY));
+            power += self.lights[i].power;
+        }
+        let node = self.nodes.len();
+        self.nodes.push(LightNode {
+            bbox,
+            power,
+            kind: LightNodeKind::Leaf(indices[0]),
+        });
+        if indices.len() > 1 {
+            //按包围盒中心沿最长轴对半分
+            let axis = bbox.longest_axis();
+            indices.sort_by(|&a, &b| {
+                let ca = center(&self.lights[a].bbox.unwrap_or(Aabb::EMPTY))[axis];
+                let cb = center(&self.lights[b].bbox.unwrap_or(Aabb::EMPTY))[axis];
+                ca.total_cmp(&cb)
+            });
+            let (left, right) = indices.split_at_mut(indices.len() / 2);
+            let left = self.build(left);
+            let right = self.build(right);
+            self.nodes[node].kind = LightNodeKind::Interior(left, right);
+        }
+        node
+    }
+
+    fn importance(&self, node: usize, origin: Point3) -> f64 {
+        let node = &self.nodes[node];
+        let bbox = &node.bbox;
+        let half_diagonal =
+            Vec3::new(bbox.x.size(), bbox.y.size(), bbox.z.size()).length_squared() / 4.0;
+        //着色点在包围盒附近时距离按包围盒尺寸截断，避免概率趋于无穷
+        let distance_squared = (center(bbox) - origin).length_squared();
+        node.power / distance_squared.max(half_diagonal)
+    }
+
+    //选左子树的概率
+    fn left_probability(&self, left: usize, right: usize, origin: Point3) -> f64 {
+        let left = self.importance(left, origin);
+        let right = self.importance(right, origin);
+        if left + right > 0.0 {
+            left / (left + right)
+        } else {
+            0.5
+        }
+    }
+}
+
+impl LightSampler for BvhLightSampler {
+    fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    fn sample(&self, origin: Point3) -> LightSample {
+        //一个随机数逐层重新缩放，整条路径只占一维
+        let mut u = random_double();
+        let p_infinite = self.infinite_probability();
+        let chosen = (u / p_infinite) as usize;
+        if chosen < self.infinite.len() {
+            return self.lights[self.infinite[chosen]]
+                .sample(origin)
+                .select(p_infinite);
+        }
+        let mut probability = 1.0 - p_infinite * self.infinite.len() as f64;
+        u = ((u - (1.0 - probability)) / probability).clamp(0.0, ONE_MINUS_EPSILON);
+        let mut node = 0;
+        loop {
+            match self.nodes[node].kind {
+                LightNodeKind::Leaf(index) => {
+                    return self.lights[index].sample(origin).select(probability);
+                }
+                LightNodeKind::Interior(left, right) => {
+                    let p = self.left_probability(left, right, origin);
+                    if u < p {
+                        u = (u / p).min(ONE_MINUS_EPSILON);
+                        probability *= p;
+                        node = left;
+                    } else {
+                        u = ((u - p) / (1.0 - p)).min(ONE_MINUS_EPSILON);
+                        probability *= 1.0 - p;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        let p_infinite = self.infinite_probability();
+        let mut total: f64 = self
+            .infinite
+            .iter()
+            .map(|&i| p_infinite * self.lights[i].pdf_value(origin, direction))
+            .sum();
+        if self.nodes.is_empty() {
+            return total;
+        }
+        let ray = Ray::new(origin, direction);
+        let p_tree = 1.0 - p_infinite * self.infinite.len() as f64;
+        let mut stack = vec![(0, p_tree)];
+        while let Some((node, probability)) = stack.pop() {
+            //光线碰不到包围盒时其中的光源都不可能生成这个方向
+            let mut ray_t = Interval::new(0.001, f64::INFINITY);
+            if probability <= 0.0 || !self.nodes[node].bbox.hit(&ray, &mut ray_t) {
+                continue;
+            }
+            match self.nodes[node].kind {
+                LightNodeKind::Leaf(index) => {
+                    total += probability * self.lights[index].pdf_value(origin, direction);
+                }
+                LightNodeKind::Interior(left, right) => {
+                    let p = self.left_probability(left, right, origin);
+                    stack.push((left, probability * p));
+                    stack.push((right, probability * (1.0 - p)));
+                }
+            }
+        }
+        total
+    }
+}
+
+fn center(bbox: &Aabb) -> Point3 {
+    Point3::new(
+        (bbox.x.min + bbox.x.max) / 2.0,
+        (bbox.y.min + bbox.y.max) / 2.0,
+        (bbox.z.min + bbox.z.max) / 2.0,
+    )
+}
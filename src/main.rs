@@ -1,43 +1,394 @@
+use clap::Parser;
 use raytracer::bvh::BvhNode;
+use raytracer::checkpoint::{Checkpoint, scene_hash};
+use raytracer::environment::{ConstantBackground, Environment, EnvironmentMap};
 use raytracer::hit_checker::HittableList;
+use raytracer::light::LightSamplerKind;
 use raytracer::material::{DiffuseLight, DummyMaterial, Lambertian, Metal};
 use raytracer::modeling::{ConstantMedium, Quad, Sphere, Translate, make_box};
 use raytracer::obj::create_model;
-use raytracer::random::random_double_range;
-use raytracer::raytracer::RayTracer;
+use raytracer::pdf::MisHeuristic;
+use raytracer::random::{random_double_range, set_seed};
+use raytracer::raytracer::{
+    AdaptiveOptions, CheckpointOptions, PathOptions, ProgressiveOptions, RenderOptions, TileOptions,
+};
+use raytracer::sampler::SamplerKind;
+use raytracer::scene::{CameraConfig, Scene, load_scene};
+use raytracer::sketchpad::{ImageFormat, SaveError};
 use raytracer::texture::{ImageTexture, MappedTexture};
+use raytracer::tile::TileOrder;
+use raytracer::tonemap::{ToneMapper, ToneMapping, TransferCurve};
 use raytracer::vec3::{Point3, Vec3};
 use raytracer::vec3color::Color;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
-fn main() {
-    final_scene();
+#[derive(Parser)]
+#[command(name = "raytracer", version, about = "离线路径追踪渲染器")]
+struct Cli {
+    /// 场景文件 (.json / .toml)，不指定时渲染内置的 final scene
+    scene: Option<String>,
+
+    /// 图像宽度（像素），覆盖场景中的 camera.image_width
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    width: Option<u32>,
+
+    /// 宽高比，覆盖场景中的 camera.aspect_ratio
+    #[arg(short, long)]
+    aspect_ratio: Option<f64>,
+
+    /// 每像素采样数，覆盖场景中的 camera.samples_per_pixel
+    #[arg(short, long, value_parser = clap::value_parser!(i32).range(1..))]
+    spp: Option<i32>,
+
+    /// 最大弹射次数，覆盖场景中的 camera.max_depth
+    #[arg(short = 'd', long, value_parser = clap::value_parser!(i32).range(1..))]
+    max_depth: Option<i32>,
+
+    /// 输出图像路径
+    #[arg(short, long, default_value = "output/final_scene.png")]
+    output: PathBuf,
+
+    /// 输出格式 (png, jpeg, ppm, bmp, tiff, exr, hdr)，默认按输出路径的扩展名推断
+    #[arg(short, long, value_parser = parse_format)]
+    format: Option<ImageFormat>,
+
+    /// JPEG 质量 (1-100)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: Option<u8>,
+
+    /// 色调映射算子 (none, reinhard, reinhard-extended, aces, hable)
+    #[arg(short, long, value_parser = parse_tone_mapper, default_value = "none")]
+    tonemap: ToneMapper,
+
+    /// 曝光补偿（档），在色调映射之前作用
+    #[arg(short, long, default_value_t = 0.0, allow_negative_numbers = true)]
+    exposure: f64,
+
+    /// reinhard-extended 的白点亮度
+    #[arg(long, value_parser = parse_white)]
+    white: Option<f64>,
+
+    /// 传递函数 (gamma2, srgb)
+    #[arg(long, value_parser = parse_transfer, default_value = "gamma2")]
+    transfer: TransferCurve,
+
+    /// 渐进式渲染：分多遍采样，并定期写出预览图
+    #[arg(long)]
+    progressive: bool,
+
+    /// 渐进式渲染时每一遍的每像素采样数，默认为 sqrt(spp)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pass_spp: Option<u32>,
+
+    /// 每渲染 N 遍写一次预览图
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    preview_every: Option<u32>,
+
+    /// 每隔 T 秒写一次预览图（默认 60）
+    #[arg(long)]
+    preview_interval: Option<f64>,
+
+    /// 预览图路径，默认直接写到输出路径
+    #[arg(long)]
+    preview: Option<PathBuf>,
+
+    /// 检查点文件路径，渲染过程中定期保存累积结果
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// 两次写检查点之间的秒数（默认 300）
+    #[arg(long, default_value_t = 300.0)]
+    checkpoint_interval: f64,
+
+    /// 从 --checkpoint 指定的检查点继续渲染
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+
+    /// tile 边长（像素）
+    #[arg(long, default_value_t = 32, value_parser = clap::value_parser!(u32).range(1..))]
+    tile_size: u32,
+
+    /// tile 渲染顺序 (scanline, spiral, hilbert)
+    #[arg(long, value_parser = parse_tile_order, default_value = "scanline")]
+    tile_order: TileOrder,
+
+    /// 自适应采样：收敛的像素提前停止，--spp 作为平均每像素的样本预算
+    #[arg(long)]
+    adaptive: bool,
+
+    /// 自适应采样时每像素的最少采样数
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u32).range(1..))]
+    min_spp: u32,
+
+    /// 自适应采样时每像素的最多采样数，默认为 4 * spp
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_spp: Option<u32>,
+
+    /// 自适应采样的收敛阈值（亮度均值的相对标准误差）
+    #[arg(long, default_value_t = 0.02)]
+    adaptive_threshold: f64,
+
+    /// 输出各像素采样数的热度图（LDR 格式，按扩展名推断）
+    #[arg(long)]
+    heatmap: Option<PathBuf>,
+
+    /// 采样器 (independent, stratified, halton, sobol, zsobol)
+    #[arg(long, value_parser = parse_sampler, default_value = "sobol")]
+    sampler: SamplerKind,
+
+    /// 漫反射弹射次数上限
+    #[arg(long)]
+    max_diffuse: Option<u32>,
+
+    /// 镜面反射/折射弹射次数上限
+    #[arg(long)]
+    max_specular: Option<u32>,
+
+    /// 介质内散射次数上限
+    #[arg(long)]
+    max_volume: Option<u32>,
+
+    /// 从第 N 次弹射开始做俄罗斯轮盘赌
+    #[arg(long, default_value_t = 3)]
+    rr_depth: u32,
+
+    /// 关闭俄罗斯轮盘赌
+    #[arg(long, conflicts_with = "rr_depth")]
+    no_roulette: bool,
+
+    /// 多重重要性采样的组合权重 (balance, power)
+    #[arg(long, value_parser = parse_mis, default_value = "power")]
+    mis: MisHeuristic,
+
+    /// 光源选择策略 (uniform, power, bvh)
+    #[arg(long, value_parser = parse_light_sampler, default_value = "bvh")]
+    light_sampler: LightSamplerKind,
+
+    /// 渲染线程数，默认使用全部核心
+    #[arg(short = 'j', long, value_parser = clap::value_parser!(usize))]
+    threads: Option<usize>,
+
+    /// 随机数种子，相同种子和参数下渲染结果逐位一致，与线程数无关
+    #[arg(long)]
+    seed: Option<u64>,
 }
 
-fn final_scene() {
-    let aspect_ratio = 16.0 / 9.0;
-    let image_width = 1600;
-    let samples_per_pixel = 10000;
-    let max_depth = 50;
-    let v_fov = 40.0;
-    let look_from = Point3::new(160.0, 325.0, 420.0);
-    let look_at = Point3::new(20.0, 120.0, 0.0);
-    let vup = Vec3::new(0.0, 1.0, 0.0);
-    let defocus_angle = 0.0;
-    let focus_dist = 10.0;
-    let background = Color::new(1.0, 1.0, 1.0);
+fn parse_format(name: &str) -> Result<ImageFormat, String> {
+    ImageFormat::from_name(name).ok_or_else(|| format!("unknown image format '{}'", name))
+}
 
-    let mut world = HittableList::default();
-    let mut lights = HittableList::default();
+fn parse_tone_mapper(name: &str) -> Result<ToneMapper, String> {
+    ToneMapper::from_name(name).ok_or_else(|| format!("unknown tone mapper '{}'", name))
+}
 
-    let star = Arc::new(DiffuseLight::from_texture(Arc::new(ImageTexture::new(
-        "star.png",
-    ))));
-    let background_star = Arc::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1000.0, star));
+fn parse_white(value: &str) -> Result<f64, String> {
+    let white: f64 = value.parse().map_err(|e| format!("{}", e))?;
+    if !white.is_finite() || white <= 0.0 {
+        return Err(format!(
+            "white point must be a positive number, got {}",
+            value
+        ));
+    }
+    Ok(white)
+}
 
-    world.add(background_star);
+fn parse_transfer(name: &str) -> Result<TransferCurve, String> {
+    TransferCurve::from_name(name).ok_or_else(|| format!("unknown transfer curve '{}'", name))
+}
+
+fn parse_sampler(name: &str) -> Result<SamplerKind, String> {
+    SamplerKind::from_name(name).ok_or_else(|| format!("unknown sampler '{}'", name))
+}
 
-    let empty_material = Arc::new(DummyMaterial);
+fn parse_mis(name: &str) -> Result<MisHeuristic, String> {
+    MisHeuristic::from_name(name).ok_or_else(|| format!("unknown MIS heuristic '{}'", name))
+}
+
+fn parse_light_sampler(name: &str) -> Result<LightSamplerKind, String> {
+    LightSamplerKind::from_name(name).ok_or_else(|| format!("unknown light sampler '{}'", name))
+}
+
+fn parse_tile_order(name: &str) -> Result<TileOrder, String> {
+    TileOrder::from_name(name).ok_or_else(|| format!("unknown tile order '{}'", name))
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    if let Some(threads) = cli.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("Cannot build the thread pool");
+    }
+    //恢复渲染且没有指定种子时沿用检查点的种子，读取失败的情况留给渲染时报告
+    let seed = cli.seed.or_else(|| {
+        let path = cli.checkpoint.as_ref().filter(|_| cli.resume)?;
+        Checkpoint::load_settings(path).ok().map(|s| s.seed)
+    });
+    if let Some(seed) = seed {
+        set_seed(seed);
+    }
+
+    //加载场景前就确定输出格式，避免渲染完才发现无法保存
+    let Some(mut format) = cli.format.or_else(|| ImageFormat::from_path(&cli.output)) else {
+        eprintln!(
+            "参数错误: {}",
+            SaveError::UnknownFormat(cli.output.display().to_string())
+        );
+        std::process::exit(2);
+    };
+    if let (ImageFormat::Jpeg { quality }, Some(q)) = (&mut format, cli.quality) {
+        *quality = q;
+    }
+    //热度图是给人看的伪彩色，只输出 LDR 格式
+    if let Some(path) = &cli.heatmap {
+        match ImageFormat::from_path(path) {
+            Some(heatmap_format) if !heatmap_format.is_hdr() => {}
+            Some(_) => {
+                eprintln!("参数错误: --heatmap must be an LDR image (png, jpeg, ppm, bmp, tiff)");
+                std::process::exit(2);
+            }
+            None => {
+                eprintln!(
+                    "参数错误: {}",
+                    SaveError::UnknownFormat(path.display().to_string())
+                );
+                std::process::exit(2);
+            }
+        }
+    }
+
+    //白点只对 reinhard-extended 有意义，用在其他算子上多半是写错了
+    let mut operator = cli.tonemap;
+    match (&mut operator, cli.white) {
+        (ToneMapper::ReinhardExtended { white }, Some(w)) => *white = w,
+        (_, Some(_)) => {
+            eprintln!("参数错误: --white only applies to --tonemap reinhard-extended");
+            std::process::exit(2);
+        }
+        _ => {}
+    }
+
+    let mut scene = match &cli.scene {
+        Some(path) => match load_scene(path) {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("场景加载失败: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => final_scene(),
+    };
+
+    //命令行参数优先于场景文件
+    let camera = &mut scene.camera;
+    if let Some(width) = cli.width {
+        camera.image_width = width;
+    }
+    if let Some(aspect_ratio) = cli.aspect_ratio {
+        camera.aspect_ratio = aspect_ratio;
+    }
+    if let Some(spp) = cli.spp {
+        camera.samples_per_pixel = spp;
+    }
+    if let Some(max_depth) = cli.max_depth {
+        camera.max_depth = max_depth;
+    }
+    if let Err(e) = camera.validate() {
+        eprintln!("参数错误: {}", e);
+        std::process::exit(2);
+    }
+
+    //指定任意预览参数都会开启渐进式渲染
+    let progressive = (cli.progressive
+        || cli.pass_spp.is_some()
+        || cli.preview_every.is_some()
+        || cli.preview_interval.is_some()
+        || cli.preview.is_some())
+    .then(|| {
+        let mut progressive = ProgressiveOptions {
+            samples_per_pass: cli.pass_spp.unwrap_or(0),
+            preview_path: cli.preview,
+            ..Default::default()
+        };
+        if cli.preview_every.is_some() || cli.preview_interval.is_some() {
+            progressive.preview_every = cli.preview_every;
+            progressive.preview_interval = cli
+                .preview_interval
+                .map(|t| Duration::from_secs_f64(t.max(0.0)));
+        }
+        progressive
+    });
+
+    let options = RenderOptions {
+        output: cli.output,
+        format: Some(format),
+        tone_mapping: ToneMapping {
+            operator,
+            exposure: cli.exposure,
+            transfer: cli.transfer,
+        },
+        progressive,
+        checkpoint: cli.checkpoint.map(|path| CheckpointOptions {
+            path,
+            interval: Duration::from_secs_f64(cli.checkpoint_interval.max(0.0)),
+            resume: cli.resume,
+        }),
+        tiles: TileOptions {
+            size: cli.tile_size,
+            order: cli.tile_order,
+        },
+        adaptive: (cli.adaptive || cli.max_spp.is_some() || cli.heatmap.is_some()).then_some(
+            AdaptiveOptions {
+                min_spp: cli.min_spp,
+                max_spp: cli.max_spp,
+                threshold: cli.adaptive_threshold,
+                heatmap: cli.heatmap,
+            },
+        ),
+        sampler: cli.sampler,
+        mis: cli.mis,
+        paths: PathOptions {
+            max_diffuse: cli.max_diffuse.unwrap_or(u32::MAX),
+            max_specular: cli.max_specular.unwrap_or(u32::MAX),
+            max_volume: cli.max_volume.unwrap_or(u32::MAX),
+            roulette_depth: (!cli.no_roulette).then_some(cli.rr_depth),
+        },
+        light_sampler: cli.light_sampler,
+        scene_hash: scene.hash,
+    };
+    if let Err(e) = scene.render(options) {
+        eprintln!("渲染失败: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn final_scene() -> Scene {
+    let camera = CameraConfig {
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 1600,
+        samples_per_pixel: 10000,
+        max_depth: 50,
+        v_fov: 40.0,
+        look_from: [160.0, 325.0, 420.0],
+        look_at: [20.0, 120.0, 0.0],
+        vup: [0.0, 1.0, 0.0],
+        defocus_angle: 0.0,
+        focus_dist: 10.0,
+    };
+    //星空背景，图片缺失时退回白色背景
+    let background: Arc<dyn Environment> = match EnvironmentMap::load("assets/star.png", 0.0, 1.0) {
+        Ok(map) => Arc::new(map),
+        Err(e) => {
+            eprintln!("{}", e);
+            Arc::new(ConstantBackground::new(Color::new(1.0, 1.0, 1.0)))
+        }
+    };
+
+    let mut world = HittableList::default();
 
     let light = Arc::new(DiffuseLight::new(Color::new(15.0, 15.0, 15.0)));
 
@@ -48,13 +399,6 @@ fn final_scene() {
         light.clone(),
     )));
 
-    lights.add(Arc::new(Quad::new(
-        Point3::new(60.0, 300.0, 500.0),
-        Vec3::new(10.0, 0.0, 0.0),
-        Vec3::new(0.0, 10.0, 0.0),
-        empty_material.clone(),
-    )));
-
     create_model(
         "assets/word.obj",
         "assets/word.mtl",
@@ -232,14 +576,13 @@ fn final_scene() {
     let mut the_world = HittableList::default();
     the_world.add(Arc::new(BvhNode::from_list(&mut world)));
 
-    let mut raytracer = RayTracer::new(
-        (aspect_ratio, image_width),
-        (look_from, look_at, vup, v_fov),
-        the_world,
-        samples_per_pixel,
-        max_depth,
-        (defocus_angle, focus_dist),
+    Scene {
+        camera,
         background,
-    );
-    raytracer.render(Arc::new(lights));
+        world: the_world,
+        lights: HittableList::default(),
+        punctual_lights: Vec::new(),
+        //内置场景没有描述文本，用名字代替
+        hash: scene_hash("final_scene"),
+    }
 }
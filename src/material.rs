@@ -1,6 +1,11 @@
 use crate::hit_checker::HitRecord;
+use crate::microfacet::{
+    MicrofacetDielectric, TrowbridgeReitz, fresnel_conductor, fresnel_dielectric, reflect, refract,
+};
+use crate::onb::ONB;
 use crate::pdf::{CosinePdf, DummyPdf, Pdf, SpherePdf};
-use crate::random::{random_double, random_unit_vector};
+use crate::punctual::spot_falloff;
+use crate::random::{random_2d, random_double, random_unit_vector};
 use crate::ray::Ray;
 use crate::texture::{SolidColor, Texture};
 use crate::vec3::{Point3, Vec3, dot, unit_vector};
@@ -13,6 +18,7 @@ pub struct ScatterRecord {
     pub pdf_ptr: Arc<dyn Pdf>,
     pub skip_pdf: bool,
     pub skip_pdf_ray: Ray,
+    pub volume: bool, //介质内的散射，单独计算弹射次数
 }
 
 impl Default for ScatterRecord {
@@ -22,11 +28,28 @@ impl Default for ScatterRecord {
             pdf_ptr: Arc::new(DummyPdf),
             skip_pdf: true,
             skip_pdf_ray: Ray::default(),
+            volume: false,
         }
     }
 }
 
-pub trait Material: Send + Sync {
+//把材质的 Arc 转成 Arc<dyn Material>，图元因此既能存具体类型也能直接存 Arc<dyn Material>
+pub trait IntoDynMaterial {
+    fn into_dyn<'a>(self: Arc<Self>) -> Arc<dyn Material + 'a>
+    where
+        Self: 'a;
+}
+
+impl<M: Material> IntoDynMaterial for M {
+    fn into_dyn<'a>(self: Arc<Self>) -> Arc<dyn Material + 'a>
+    where
+        Self: 'a,
+    {
+        self
+    }
+}
+
+pub trait Material: Send + Sync + IntoDynMaterial {
     fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
         0.0
     }
@@ -34,9 +57,25 @@ pub trait Material: Send + Sync {
         false
     }
 
+    //BSDF 乘以余弦项，rec 和 s_rec 为 scatter 之后的结果。
+    //反照率和方向无关的材质只需实现 scattering_pdf
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, s_rec: &ScatterRecord, scattered: &Ray) -> Color {
+        s_rec.attenuation * self.scattering_pdf(r_in, rec, scattered)
+    }
+
     fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: &Point3) -> Color {
         Color::new(0.0, 0.0, 0.0)
     }
+
+    //表面上的平均发光亮度，用来估计光源功率
+    fn average_emission(&self) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    //(u, v) 处的不透明度，三角形等图元按它随机剔除交点来实现镂空
+    fn alpha(&self, _u: f64, _v: f64) -> f64 {
+        1.0
+    }
 }
 
 #[derive(Default)]
@@ -44,21 +83,14 @@ pub struct DummyMaterial;
 
 impl Material for DummyMaterial {}
 
-pub struct Lambertian<T: Texture> {
+pub struct Lambertian<T: Texture + ?Sized> {
     pub tex: Arc<T>,
 }
 
-impl<T: Texture> Lambertian<T> {
+impl<T: Texture + ?Sized> Lambertian<T> {
     pub fn from_tex(tex: Arc<T>) -> Self {
         Self { tex }
     }
-
-    pub fn alpha(&self, u: f64, v: f64) -> f64 {
-        match self.tex.alpha(u, v) {
-            Some(a) => a.sqrt(),
-            _ => 0.0,
-        }
-    }
 }
 
 impl Lambertian<SolidColor> {
@@ -69,7 +101,7 @@ impl Lambertian<SolidColor> {
     }
 }
 
-impl<T: Texture> Material for Lambertian<T> {
+impl<T: Texture + ?Sized> Material for Lambertian<T> {
     fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
         let cos_theta = dot(&rec.normal, &unit_vector(scattered.direction()));
         if cos_theta < 0.0 { 0.0 } else { cos_theta / PI }
@@ -89,6 +121,10 @@ impl<T: Texture> Material for Lambertian<T> {
         s_rec.skip_pdf = false;
         true
     }
+
+    fn alpha(&self, u: f64, v: f64) -> f64 {
+        self.tex.alpha(u, v).unwrap_or(1.0)
+    }
 }
 
 pub struct Metal {
@@ -161,11 +197,204 @@ impl Material for Dielectric {
     }
 }
 
-pub struct DiffuseLight<T: Texture> {
+//常见金属在 R、G、B 代表波长处的复折射率 (eta, k)
+pub fn conductor_preset(name: &str) -> Option<(Color, Color)> {
+    let (eta, k) = match name.to_ascii_lowercase().as_str() {
+        "gold" => ([0.143, 0.374, 1.442], [3.983, 2.385, 1.603]),
+        "silver" => ([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+        "copper" => ([0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+        "aluminum" | "aluminium" => ([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+        "iron" => ([2.870, 2.950, 2.650], [3.080, 2.930, 2.810]),
+        _ => return None,
+    };
+    Some((
+        Color::new(eta[0], eta[1], eta[2]),
+        Color::new(k[0], k[1], k[2]),
+    ))
+}
+
+//粗糙度沿切线和副切线方向，两者不同时为各向异性
+fn anisotropic_distribution((roughness_u, roughness_v): (f64, f64)) -> TrowbridgeReitz {
+    TrowbridgeReitz::new(roughness_u * roughness_u, roughness_v * roughness_v)
+}
+
+//着色坐标系和其中指向观察者的方向
+fn shading_frame(r_in: &Ray, rec: &HitRecord) -> (ONB, Vec3) {
+    let frame = ONB::from_normal_tangent(&rec.normal, &rec.tangent);
+    let wo = frame.to_local(-unit_vector(r_in.direction()));
+    (frame, wo)
+}
+
+//GGX 微表面导体，菲涅尔项由复折射率给出
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    distribution: TrowbridgeReitz,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: (f64, f64)) -> Self {
+        Self {
+            eta,
+            k,
+            distribution: anisotropic_distribution(roughness),
+        }
+    }
+
+    pub fn from_preset(name: &str, roughness: (f64, f64)) -> Option<Self> {
+        conductor_preset(name).map(|(eta, k)| Self::new(eta, k, roughness))
+    }
+
+    fn fresnel(&self, cos_theta: f64) -> Color {
+        Color::new(
+            fresnel_conductor(cos_theta, self.eta.x(), self.k.x()),
+            fresnel_conductor(cos_theta, self.eta.y(), self.k.y()),
+            fresnel_conductor(cos_theta, self.eta.z(), self.k.z()),
+        )
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, s_rec: &mut ScatterRecord) -> bool {
+        let (frame, wo) = shading_frame(r_in, rec);
+        if self.distribution.effectively_smooth() {
+            s_rec.attenuation = self.fresnel(wo.z());
+            s_rec.pdf_ptr = Arc::new(DummyPdf);
+            s_rec.skip_pdf = true;
+            let direction = frame.transform(reflect(wo, Vec3::new(0.0, 0.0, 1.0)));
+            s_rec.skip_pdf_ray = Ray::new_with_time(rec.pos, direction, r_in.time());
+            return true;
+        }
+        s_rec.attenuation = Color::new(1.0, 1.0, 1.0);
+        s_rec.pdf_ptr = Arc::new(MicrofacetReflectionPdf {
+            frame,
+            wo,
+            distribution: self.distribution,
+        });
+        s_rec.skip_pdf = false;
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, _s_rec: &ScatterRecord, scattered: &Ray) -> Color {
+        let (frame, wo) = shading_frame(r_in, rec);
+        let wi = frame.to_local(unit_vector(scattered.direction()));
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let wm = unit_vector(&(wo + wi));
+        //BSDF 乘以 cosθi
+        self.fresnel(dot(&wo, &wm)) * self.distribution.d(wm) * self.distribution.g(wo, wi)
+            / (4.0 * wo.z())
+    }
+}
+
+//按可见法线分布采样微表面法线后镜面反射
+struct MicrofacetReflectionPdf {
+    frame: ONB,
+    wo: Vec3,
+    distribution: TrowbridgeReitz,
+}
+
+impl Pdf for MicrofacetReflectionPdf {
+    fn value(&self, direction: Vec3) -> f64 {
+        let wi = self.frame.to_local(unit_vector(&direction));
+        let wm = self.wo + wi;
+        if self.wo.z() <= 0.0 || wm.near_zero() {
+            return 0.0;
+        }
+        //反射到表面以下的样本也计入密度，它们的 BSDF 为 0
+        let wm = unit_vector(&wm);
+        self.distribution.pdf(self.wo, wm) / (4.0 * dot(&self.wo, &wm))
+    }
+
+    fn generate(&self) -> Vec3 {
+        let wm = self.distribution.sample_wm(self.wo, random_2d());
+        self.frame.transform(reflect(self.wo, wm))
+    }
+}
+
+//GGX 微表面电介质，按可见法线分布采样，反射和折射都能参与 MIS
+pub struct RoughDielectric {
+    refraction_index: f64,
+    distribution: TrowbridgeReitz,
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_index: f64, roughness: (f64, f64)) -> Self {
+        Self {
+            refraction_index,
+            distribution: anisotropic_distribution(roughness),
+        }
+    }
+
+    fn interface(&self, rec: &HitRecord) -> MicrofacetDielectric {
+        let eta = if rec.front_face {
+            self.refraction_index
+        } else {
+            1.0 / self.refraction_index
+        };
+        MicrofacetDielectric::new(self.distribution, eta)
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, s_rec: &mut ScatterRecord) -> bool {
+        let (frame, wo) = shading_frame(r_in, rec);
+        let interface = self.interface(rec);
+        s_rec.attenuation = Color::new(1.0, 1.0, 1.0);
+        if self.distribution.effectively_smooth() {
+            //光滑界面按精确的菲涅尔项选择反射或折射
+            let n = Vec3::new(0.0, 0.0, 1.0);
+            let wi = if random_double() < fresnel_dielectric(wo.z(), interface.eta) {
+                reflect(wo, n)
+            } else {
+                refract(wo, n, interface.eta).unwrap_or_else(|| reflect(wo, n))
+            };
+            s_rec.pdf_ptr = Arc::new(DummyPdf);
+            s_rec.skip_pdf = true;
+            s_rec.skip_pdf_ray = Ray::new_with_time(rec.pos, frame.transform(wi), r_in.time());
+            return true;
+        }
+        s_rec.pdf_ptr = Arc::new(MicrofacetDielectricPdf {
+            frame,
+            wo,
+            interface,
+        });
+        s_rec.skip_pdf = false;
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, _s_rec: &ScatterRecord, scattered: &Ray) -> Color {
+        let (frame, wo) = shading_frame(r_in, rec);
+        let wi = frame.to_local(unit_vector(scattered.direction()));
+        let f = self.interface(rec).f(wo, wi);
+        Color::new(f, f, f)
+    }
+}
+
+struct MicrofacetDielectricPdf {
+    frame: ONB,
+    wo: Vec3,
+    interface: MicrofacetDielectric,
+}
+
+impl Pdf for MicrofacetDielectricPdf {
+    fn value(&self, direction: Vec3) -> f64 {
+        self.interface
+            .pdf(self.wo, self.frame.to_local(unit_vector(&direction)))
+    }
+
+    fn generate(&self) -> Vec3 {
+        let wi = self.interface.sample(self.wo, random_double(), random_2d());
+        self.frame.transform(wi)
+    }
+}
+
+pub struct DiffuseLight<T: Texture + ?Sized> {
     tex: Arc<T>,
 }
 
-impl<T: Texture> DiffuseLight<T> {
+impl<T: Texture + ?Sized> DiffuseLight<T> {
     pub fn from_texture(tex: Arc<T>) -> Self {
         Self { tex }
     }
@@ -179,17 +408,77 @@ impl DiffuseLight<SolidColor> {
     }
 }
 
-impl<T: Texture> Material for DiffuseLight<T> {
+impl<T: Texture + ?Sized> Material for DiffuseLight<T> {
     fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, u: f64, v: f64, p: &Point3) -> Color {
         self.tex.value(u, v, p)
     }
+
+    fn average_emission(&self) -> Color {
+        average_texture(self.tex.as_ref())
+    }
+}
+
+//在 uv 上取 8x8 个点平均，图片纹理只是粗略估计
+fn average_texture<T: Texture + ?Sized>(tex: &T) -> Color {
+    let n = 8;
+    let mut sum = Color::new(0.0, 0.0, 0.0);
+    for i in 0..n {
+        for j in 0..n {
+            let u = (i as f64 + 0.5) / n as f64;
+            let v = (j as f64 + 0.5) / n as f64;
+            sum += tex.value(u, v, &Point3::default());
+        }
+    }
+    sum / (n * n) as f64
+}
+
+//只从正面发光、亮度随出射方向与法线的夹角按聚光灯方式衰减的面光源
+pub struct AreaSpotLight<T: Texture + ?Sized> {
+    tex: Arc<T>,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl<T: Texture + ?Sized> AreaSpotLight<T> {
+    //角度为半角（度）
+    pub fn from_texture(tex: Arc<T>, inner_angle: f64, outer_angle: f64) -> Self {
+        let outer = outer_angle.clamp(0.0, 90.0);
+        let inner = inner_angle.clamp(0.0, outer);
+        Self {
+            tex,
+            cos_inner: inner.to_radians().cos(),
+            cos_outer: outer.to_radians().cos(),
+        }
+    }
+}
+
+impl AreaSpotLight<SolidColor> {
+    pub fn new(emit: Color, inner_angle: f64, outer_angle: f64) -> Self {
+        Self::from_texture(Arc::new(SolidColor::new(emit)), inner_angle, outer_angle)
+    }
+}
+
+impl<T: Texture + ?Sized> Material for AreaSpotLight<T> {
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Point3) -> Color {
+        if !rec.front_face {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let cos_theta = dot(&-unit_vector(r_in.direction()), &rec.normal);
+        self.tex.value(u, v, p) * spot_falloff(cos_theta, self.cos_inner, self.cos_outer)
+    }
+
+    fn average_emission(&self) -> Color {
+        //衰减区按一半计入，相对于漫射面光源的功率比例为 1 - cos²
+        let cos_mid = (self.cos_inner + self.cos_outer) / 2.0;
+        average_texture(self.tex.as_ref()) * (1.0 - cos_mid * cos_mid)
+    }
 }
 
 pub struct Isotropic<T: Texture + ?Sized> {
     tex: Arc<T>,
 }
 
-impl<T: Texture> Isotropic<T> {
+impl<T: Texture + ?Sized> Isotropic<T> {
     pub fn new_from_texture(tex: Arc<T>) -> Self {
         Self { tex }
     }
@@ -203,7 +492,7 @@ impl Isotropic<SolidColor> {
     }
 }
 
-impl<T: Texture> Material for Isotropic<T> {
+impl<T: Texture + ?Sized> Material for Isotropic<T> {
     fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
         1.0 / (4.0 * PI)
     }
@@ -212,6 +501,49 @@ impl<T: Texture> Material for Isotropic<T> {
         s_rec.attenuation = self.tex.value(rec.u, rec.v, &rec.pos);
         s_rec.pdf_ptr = Arc::new(SpherePdf::new());
         s_rec.skip_pdf = false;
+        s_rec.volume = true;
         true
     }
 }
+
+//给任意材质加上纹理控制的镂空：纹理自带 alpha 时直接使用，否则取颜色的 r 通道作为不透明度
+pub struct AlphaMask<M: Material + ?Sized, T: Texture + ?Sized> {
+    material: Arc<M>,
+    mask: Arc<T>,
+}
+
+impl<M: Material + ?Sized, T: Texture + ?Sized> AlphaMask<M, T> {
+    pub fn new(material: Arc<M>, mask: Arc<T>) -> Self {
+        Self { material, mask }
+    }
+}
+
+impl<M: Material + ?Sized, T: Texture + ?Sized> Material for AlphaMask<M, T> {
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.material.scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, s_rec: &mut ScatterRecord) -> bool {
+        self.material.scatter(r_in, rec, s_rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, s_rec: &ScatterRecord, scattered: &Ray) -> Color {
+        self.material.eval(r_in, rec, s_rec, scattered)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Point3) -> Color {
+        self.material.emitted(r_in, rec, u, v, p)
+    }
+
+    fn average_emission(&self) -> Color {
+        self.material.average_emission()
+    }
+
+    fn alpha(&self, u: f64, v: f64) -> f64 {
+        let opacity = match self.mask.alpha(u, v) {
+            Some(a) => a,
+            None => self.mask.value(u, v, &Point3::default()).x(),
+        };
+        opacity.clamp(0.0, 1.0) * self.material.alpha(u, v)
+    }
+}
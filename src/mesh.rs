@@ -0,0 +1,595 @@
+use crate::aabb::Aabb;
+use crate::hit_checker::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::onb::ONB;
+use crate::random::{random_2d, random_double};
+use crate::ray::Ray;
+use crate::tonemap::luminance;
+use crate::uv::UV;
+use crate::vec3::{Point3, Vec3, cross, dot, unit_vector};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+//叶节点最多包含的三角形数
+const LEAF_SIZE: usize = 4;
+
+//Möller–Trumbore 求交，返回 t 和重心坐标 (u, v)，对应顶点 1、2 的权重
+fn intersect_triangle(
+    ray: &Ray,
+    ray_t: Interval,
+    p0: Point3,
+    e1: Vec3,
+    e2: Vec3,
+) -> Option<(f64, f64, f64)> {
+    let h = cross(ray.direction(), &e2);
+    let a = dot(&e1, &h);
+    if a.abs() < 1e-8 {
+        return None;
+    }
+
+    let f = 1.0 / a;
+    let s = *ray.origin() - p0;
+    let u = f * dot(&s, &h);
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = cross(&s, &e1);
+    let v = f * dot(ray.direction(), &q);
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = f * dot(&e2, &q);
+    if !ray_t.contains(t) {
+        return None;
+    }
+    Some((t, u, v))
+}
+
+//由 uv 的变化求三角形的切线和副切线（沿 u、v 增大的方向），uv 退化时返回 None
+fn uv_tangents(e1: Vec3, e2: Vec3, delta_uv1: UV, delta_uv2: UV) -> Option<(Vec3, Vec3)> {
+    let det = delta_uv1.u() * delta_uv2.v() - delta_uv1.v() * delta_uv2.u();
+    if det.abs() < 1e-12 || !det.is_finite() {
+        return None;
+    }
+    let r = 1.0 / det;
+    let tangent = (e1 * delta_uv2.v() - e2 * delta_uv1.v()) * r;
+    let bitangent = (e2 * delta_uv1.u() - e1 * delta_uv2.u()) * r;
+    Some((tangent, bitangent))
+}
+
+//三角形在顶点 p0 处的内角
+fn corner_angle(p0: Point3, p1: Point3, p2: Point3) -> f64 {
+    let a = p1 - p0;
+    let b = p2 - p0;
+    let len = a.length() * b.length();
+    if len <= 0.0 {
+        return 0.0;
+    }
+    (dot(&a, &b) / len).clamp(-1.0, 1.0).acos()
+}
+
+//-0.0 和 0.0 视为同一个位置
+fn position_key(p: Point3) -> [u64; 3] {
+    [
+        (p.x() + 0.0).to_bits(),
+        (p.y() + 0.0).to_bits(),
+        (p.z() + 0.0).to_bits(),
+    ]
+}
+
+//count 为 0 的是内部节点，左子节点紧跟在它后面，右子节点的下标为 right
+struct MeshNode {
+    bbox: Aabb,
+    start: u32,
+    count: u32,
+    right: u32,
+    axis: u8,
+}
+
+//共享顶点缓冲的三角形网格，三角形按下标引用顶点，自带一棵 BVH
+pub struct TriangleMesh<M: Material + ?Sized> {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,         //与 positions 一一对应，为空时只用面法线
+    uvs: Vec<UV>,               //与 positions 一一对应，为空时 uv 为 0
+    tangents: Vec<(Vec3, f64)>, //顶点切线和副切线的朝向 (±1)，没有法线或 uv 时为空
+    indices: Vec<[u32; 3]>,
+    mat: Arc<M>,
+    nodes: Vec<MeshNode>,
+    area: f64,
+    area_cdf: Vec<f64>, //按面积采样三角形
+}
+
+impl<M: Material + ?Sized> TriangleMesh<M> {
+    pub fn new(
+        positions: Vec<Point3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<UV>,
+        indices: Vec<[u32; 3]>,
+        mat: Arc<M>,
+    ) -> Self {
+        //文件中的法线不一定是单位向量，长度为 0 的保留为 0，着色时退回面法线
+        let normals = normals
+            .into_iter()
+            .map(|n| {
+                if n.length_squared() > 0.0 {
+                    unit_vector(&n)
+                } else {
+                    Vec3::default()
+                }
+            })
+            .collect();
+        let mut mesh = Self {
+            positions,
+            normals,
+            uvs,
+            tangents: Vec::new(),
+            indices,
+            mat,
+            nodes: Vec::new(),
+            area: 0.0,
+            area_cdf: Vec::new(),
+        };
+        mesh.build_bvh();
+        mesh.build_tangents();
+
+        let areas: Vec<f64> = (0..mesh.indices.len())
+            .map(|i| mesh.triangle_area(i))
+            .collect();
+        let mut sum = 0.0;
+        mesh.area_cdf = areas
+            .iter()
+            .map(|a| {
+                sum += a;
+                sum
+            })
+            .collect();
+        mesh.area = sum;
+        mesh
+    }
+
+    //没有顶点法线时按角度加权生成，相邻面法线夹角超过 crease_angle（度）的地方保留折痕
+    pub fn with_smooth_normals(
+        positions: Vec<Point3>,
+        uvs: Vec<UV>,
+        indices: Vec<[u32; 3]>,
+        crease_angle: f64,
+        mat: Arc<M>,
+    ) -> Self {
+        let face_normals: Vec<Vec3> = indices
+            .iter()
+            .map(|&[a, b, c]| {
+                let (p0, p1, p2) = (
+                    positions[a as usize],
+                    positions[b as usize],
+                    positions[c as usize],
+                );
+                let n = cross(&(p1 - p0), &(p2 - p0));
+                if n.length_squared() > 0.0 {
+                    unit_vector(&n)
+                } else {
+                    Vec3::default()
+                }
+            })
+            .collect();
+
+        //位置相同的顶点（包括 uv 接缝两侧的顶点）属于同一组，每组的角连续存放在 corners 中
+        let mut group_ids: HashMap<[u64; 3], u32> = HashMap::new();
+        let vertex_group: Vec<u32> = positions
+            .iter()
+            .map(|&p| {
+                let next = group_ids.len() as u32;
+                *group_ids.entry(position_key(p)).or_insert(next)
+            })
+            .collect();
+        let mut offsets = vec![0u32; group_ids.len() + 1];
+        drop(group_ids);
+        for &vertex in indices.iter().flatten() {
+            offsets[vertex_group[vertex as usize] as usize + 1] += 1;
+        }
+        for i in 1..offsets.len() {
+            offsets[i] += offsets[i - 1];
+        }
+        let mut fill = offsets.clone();
+        let mut corners = vec![(0u32, 0.0); indices.len() * 3];
+        for (face, &[a, b, c]) in indices.iter().enumerate() {
+            let (p0, p1, p2) = (
+                positions[a as usize],
+                positions[b as usize],
+                positions[c as usize],
+            );
+            let angles = [
+                corner_angle(p0, p1, p2),
+                corner_angle(p1, p2, p0),
+                corner_angle(p2, p0, p1),
+            ];
+            for (vertex, angle) in [a, b, c].into_iter().zip(angles) {
+                let group = vertex_group[vertex as usize] as usize;
+                corners[fill[group] as usize] = (face as u32, angle);
+                fill[group] += 1;
+            }
+        }
+        drop(fill);
+
+        //每个角只累加与所在面夹角不超过折痕角的面，法线不同的角拆成不同的顶点；
+        //first 记录顶点第一次生成的副本，拆出来的其余副本放在 split 中
+        let cos_crease = crease_angle.to_radians().cos() - 1e-9;
+        let mut first = vec![u32::MAX; positions.len()];
+        let mut split: HashMap<(u32, [u64; 3]), u32> = HashMap::new();
+        let mut new_positions = Vec::with_capacity(positions.len());
+        let mut new_normals = Vec::with_capacity(positions.len());
+        let mut new_uvs = Vec::with_capacity(uvs.len());
+        let mut new_indices = Vec::with_capacity(indices.len());
+        for (face, triangle) in indices.iter().enumerate() {
+            let face_normal = face_normals[face];
+            let mut new_triangle = [0; 3];
+            for (slot, &vertex) in new_triangle.iter_mut().zip(triangle) {
+                let group = vertex_group[vertex as usize] as usize;
+                let (start, end) = (offsets[group] as usize, offsets[group + 1] as usize);
+                let mut sum = Vec3::default();
+                for &(other, angle) in &corners[start..end] {
+                    let other_normal = face_normals[other as usize];
+                    if dot(&face_normal, &other_normal) >= cos_crease {
+                        sum += angle * other_normal;
+                    }
+                }
+                let normal = if sum.length_squared() > 0.0 {
+                    unit_vector(&sum)
+                } else if face_normal.length_squared() > 0.0 {
+                    face_normal
+                } else {
+                    Vec3::new(0.0, 0.0, 1.0)
+                };
+
+                let key = position_key(normal);
+                let existing = match first[vertex as usize] {
+                    u32::MAX => None,
+                    index if position_key(new_normals[index as usize]) == key => Some(index),
+                    _ => split.get(&(vertex, key)).copied(),
+                };
+                *slot = existing.unwrap_or_else(|| {
+                    let index = new_positions.len() as u32;
+                    new_positions.push(positions[vertex as usize]);
+                    new_normals.push(normal);
+                    if !uvs.is_empty() {
+                        new_uvs.push(uvs[vertex as usize]);
+                    }
+                    if first[vertex as usize] == u32::MAX {
+                        first[vertex as usize] = index;
+                    } else {
+                        split.insert((vertex, key), index);
+                    }
+                    index
+                });
+            }
+            new_indices.push(new_triangle);
+        }
+
+        Self::new(new_positions, new_normals, new_uvs, new_indices, mat)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn vertices(&self, i: usize) -> (Point3, Point3, Point3) {
+        let [a, b, c] = self.indices[i];
+        (
+            self.positions[a as usize],
+            self.positions[b as usize],
+            self.positions[c as usize],
+        )
+    }
+
+    fn triangle_area(&self, i: usize) -> f64 {
+        let (p0, p1, p2) = self.vertices(i);
+        cross(&(p1 - p0), &(p2 - p0)).length() / 2.0
+    }
+
+    fn triangle_bbox(&self, i: usize) -> Aabb {
+        let (p0, p1, p2) = self.vertices(i);
+        Aabb::from_box(Aabb::from_points(p0, p1), Aabb::from_points(p0, p2))
+    }
+
+    fn uv(&self, i: usize, u: f64, v: f64) -> UV {
+        if self.uvs.is_empty() {
+            return UV::default();
+        }
+        let [a, b, c] = self.indices[i];
+        self.uvs[a as usize] * (1.0 - u - v) + self.uvs[b as usize] * u + self.uvs[c as usize] * v
+    }
+
+    fn build_bvh(&mut self) {
+        if self.indices.is_empty() {
+            return;
+        }
+        let bounds: Vec<Aabb> = (0..self.indices.len())
+            .map(|i| self.triangle_bbox(i))
+            .collect();
+        let centroids: Vec<Point3> = (0..self.indices.len())
+            .map(|i| {
+                let (p0, p1, p2) = self.vertices(i);
+                (p0 + p1 + p2) / 3.0
+            })
+            .collect();
+        let mut order: Vec<u32> = (0..self.indices.len() as u32).collect();
+        let mut nodes = Vec::with_capacity(2 * self.indices.len() / LEAF_SIZE + 1);
+        build_node(&mut nodes, &mut order, 0, &bounds, &centroids);
+        //按 BVH 的顺序重排三角形，叶节点引用一段连续的下标
+        self.indices = order.iter().map(|&i| self.indices[i as usize]).collect();
+        self.nodes = nodes;
+    }
+
+    //MikkTSpace 的做法：每个面的 uv 切线投影到顶点法线的切平面上，按内角加权累加，
+    //副切线只记录相对 cross(normal, tangent) 的朝向；uv 退化的面不参与
+    fn build_tangents(&mut self) {
+        if self.normals.is_empty() || self.uvs.is_empty() {
+            return;
+        }
+        let mut tangents = vec![Vec3::default(); self.positions.len()];
+        let mut bitangents = vec![Vec3::default(); self.positions.len()];
+        for &[a, b, c] in &self.indices {
+            let corners = [a as usize, b as usize, c as usize];
+            let [p0, p1, p2] = corners.map(|i| self.positions[i]);
+            let [uv0, uv1, uv2] = corners.map(|i| self.uvs[i]);
+            let Some((tangent, bitangent)) = uv_tangents(p1 - p0, p2 - p0, uv1 - uv0, uv2 - uv0)
+            else {
+                continue;
+            };
+            let angles = [
+                corner_angle(p0, p1, p2),
+                corner_angle(p1, p2, p0),
+                corner_angle(p2, p0, p1),
+            ];
+            for (i, angle) in corners.into_iter().zip(angles) {
+                let n = self.normals[i];
+                let t = tangent - n * dot(&tangent, &n);
+                let b = bitangent - n * dot(&bitangent, &n);
+                if t.length_squared() > 0.0 {
+                    tangents[i] += angle * unit_vector(&t);
+                }
+                if b.length_squared() > 0.0 {
+                    bitangents[i] += angle * unit_vector(&b);
+                }
+            }
+        }
+
+        self.tangents = (0..self.positions.len())
+            .map(|i| {
+                let n = self.normals[i];
+                if n.length_squared() <= 0.0 {
+                    return (Vec3::new(1.0, 0.0, 0.0), 1.0);
+                }
+                //没有可用的 uv 切线时由副切线推出，都没有时任取一个与法线垂直的方向
+                let tangent = if tangents[i].near_zero() && !bitangents[i].near_zero() {
+                    cross(&bitangents[i], &n)
+                } else {
+                    tangents[i]
+                };
+                let tangent = ONB::from_normal_tangent(&n, &tangent).u();
+                let sign = if dot(&cross(&n, &tangent), &bitangents[i]) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                (tangent, sign)
+            })
+            .collect();
+    }
+
+    //依次访问与光线相交的三角形，visit 返回 true 时把 t 作为之后的上界
+    fn traverse(
+        &self,
+        ray: &Ray,
+        ray_t: Interval,
+        mut visit: impl FnMut(usize, f64, f64, f64) -> bool,
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut max = ray_t.max;
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0usize);
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let mut node_t = Interval::new(ray_t.min, max);
+            if !node.bbox.hit(ray, &mut node_t) {
+                continue;
+            }
+            if node.count == 0 {
+                //先访问光线方向上较近的子节点
+                if ray.direction()[node.axis as usize] < 0.0 {
+                    stack.push(index + 1);
+                    stack.push(node.right as usize);
+                } else {
+                    stack.push(node.right as usize);
+                    stack.push(index + 1);
+                }
+                continue;
+            }
+            let start = node.start as usize;
+            for i in start..start + node.count as usize {
+                let (p0, p1, p2) = self.vertices(i);
+                let interval = Interval::new(ray_t.min, max);
+                if let Some((t, u, v)) = intersect_triangle(ray, interval, p0, p1 - p0, p2 - p0) {
+                    if visit(i, t, u, v) {
+                        max = t;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn build_node(
+    nodes: &mut Vec<MeshNode>,
+    order: &mut [u32],
+    start: usize,
+    bounds: &[Aabb],
+    centroids: &[Point3],
+) -> usize {
+    let bbox = order
+        .iter()
+        .fold(Aabb::EMPTY, |b, &i| Aabb::from_box(b, bounds[i as usize]));
+    let index = nodes.len();
+    nodes.push(MeshNode {
+        bbox,
+        start: start as u32,
+        count: order.len() as u32,
+        right: 0,
+        axis: 0,
+    });
+    if order.len() <= LEAF_SIZE {
+        return index;
+    }
+
+    //按三角形中心的包围盒最长轴取中位数划分
+    let centroid_box = order.iter().fold(Aabb::EMPTY, |b, &i| {
+        let c = centroids[i as usize];
+        Aabb::from_box(b, Aabb::from_points(c, c))
+    });
+    let axis = centroid_box.longest_axis();
+    let mid = order.len() / 2;
+    order.select_nth_unstable_by(mid, |&a, &b| {
+        centroids[a as usize][axis]
+            .partial_cmp(&centroids[b as usize][axis])
+            .unwrap_or(Ordering::Equal)
+    });
+    let (left, right) = order.split_at_mut(mid);
+    build_node(nodes, left, start, bounds, centroids);
+    let right = build_node(nodes, right, start + mid, bounds, centroids);
+    nodes[index].count = 0;
+    nodes[index].right = right as u32;
+    nodes[index].axis = axis as u8;
+    index
+}
+
+impl<M: Material + ?Sized + 'static> Hittable for TriangleMesh<M> {
+    fn hit(&self, ray: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let mut closest = None;
+        self.traverse(ray, ray_t, |i, t, u, v| {
+            //按材质的不透明度随机剔除
+            let uv = self.uv(i, u, v);
+            let opacity = self.mat.alpha(uv.u(), uv.v());
+            if opacity < 1.0 && random_double() >= opacity {
+                return false;
+            }
+            closest = Some((i, t, u, v));
+            true
+        });
+        let Some((i, t, u, v)) = closest else {
+            return false;
+        };
+
+        let (p0, p1, p2) = self.vertices(i);
+        let (e1, e2) = (p1 - p0, p2 - p0);
+        let true_normal = unit_vector(&cross(&e1, &e2));
+        let [a, b, c] = self.indices[i].map(|index| index as usize);
+        let w = 1.0 - u - v;
+        //插值的顶点法线作为着色法线，和面法线不在同一侧时说明绕序与法线相反，翻转过来
+        let normal = if self.normals.is_empty() {
+            true_normal
+        } else {
+            let n = self.normals[a] * w + self.normals[b] * u + self.normals[c] * v;
+            if n.length_squared() <= 0.0 {
+                true_normal
+            } else if dot(&n, &true_normal) < 0.0 {
+                -unit_vector(&n)
+            } else {
+                unit_vector(&n)
+            }
+        };
+
+        let uv = self.uv(i, u, v);
+        let (tangent, sign) = if !self.tangents.is_empty() {
+            let (t0, s0) = self.tangents[a];
+            let (t1, s1) = self.tangents[b];
+            let (t2, s2) = self.tangents[c];
+            let sign = if s0 * w + s1 * u + s2 * v < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            (t0 * w + t1 * u + t2 * v, sign)
+        } else if self.uvs.is_empty() {
+            (e1, 1.0)
+        } else {
+            let (delta_uv1, delta_uv2) = (self.uvs[b] - self.uvs[a], self.uvs[c] - self.uvs[a]);
+            match uv_tangents(e1, e2, delta_uv1, delta_uv2) {
+                Some((tangent, bitangent)) => {
+                    let sign = if dot(&cross(&normal, &tangent), &bitangent) < 0.0 {
+                        -1.0
+                    } else {
+                        1.0
+                    };
+                    (tangent, sign)
+                }
+                None => (e1, 1.0),
+            }
+        };
+        let tangent = ONB::from_normal_tangent(&normal, &tangent).u();
+
+        rec.u = uv.u();
+        rec.v = uv.v();
+        rec.t = t;
+        rec.pos = ray.at(t);
+        rec.mat = self.mat.clone().into_dyn();
+        rec.tangent = tangent;
+        rec.bitangent = sign * cross(&normal, &tangent);
+        rec.set_face_normal(ray, true_normal);
+        rec.normal = if rec.front_face { normal } else { -normal };
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::EMPTY, |node| node.bbox)
+    }
+
+    //方向上每个交点都可能被采样到，pdf 对所有交点求和
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        if self.area <= 0.0 {
+            return 0.0;
+        }
+        let ray = Ray::new(origin, direction);
+        let mut pdf = 0.0;
+        self.traverse(&ray, Interval::new(0.001, f64::INFINITY), |i, t, _, _| {
+            let (p0, p1, p2) = self.vertices(i);
+            let normal = unit_vector(&cross(&(p1 - p0), &(p2 - p0)));
+            let distance_squared = t * t * direction.length_squared();
+            let cosine = (dot(&direction, &normal) / direction.length()).abs();
+            if cosine > 0.0 {
+                pdf += distance_squared / (cosine * self.area);
+            }
+            false
+        });
+        pdf
+    }
+
+    //按面积选一个三角形，再在三角形内均匀采样
+    fn random(&self, origin: Vec3) -> Vec3 {
+        let Some(&total) = self.area_cdf.last() else {
+            return Vec3::new(1.0, 0.0, 0.0);
+        };
+        let target = random_double() * total;
+        let i = self
+            .area_cdf
+            .partition_point(|&c| c < target)
+            .min(self.indices.len() - 1);
+        let (p0, p1, p2) = self.vertices(i);
+        let (r1, r2) = random_2d();
+        let (r1, r2) = if r1 + r2 > 1.0 {
+            (1.0 - r1, 1.0 - r2)
+        } else {
+            (r1, r2)
+        };
+        p0 + r1 * (p1 - p0) + r2 * (p2 - p0) - origin
+    }
+
+    fn power(&self) -> f64 {
+        PI * self.area * luminance(&self.mat.average_emission())
+    }
+}
@@ -0,0 +1,252 @@
+use crate::vec3::{Vec3, cross, dot, unit_vector};
+use std::f64::consts::PI;
+
+//以下函数都在着色坐标系中计算，z 轴为表面法线
+
+//GGX（Trowbridge-Reitz）微表面法线分布，alpha_x、alpha_y 不同时为各向异性
+#[derive(Clone, Copy)]
+pub struct TrowbridgeReitz {
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        //alpha 过小时分布接近 δ 函数，数值上不稳定
+        Self {
+            alpha_x: alpha_x.max(1e-4),
+            alpha_y: alpha_y.max(1e-4),
+        }
+    }
+
+    pub fn isotropic(alpha: f64) -> Self {
+        Self::new(alpha, alpha)
+    }
+
+    //足够光滑时按理想镜面处理
+    pub fn effectively_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    //美术上常用的粗糙度 -> alpha
+    pub fn roughness_to_alpha(roughness: f64) -> f64 {
+        (roughness * roughness).max(1e-3)
+    }
+
+    //微表面法线总在上半球
+    pub fn d(&self, wm: Vec3) -> f64 {
+        if wm.z() <= 0.0 {
+            return 0.0;
+        }
+        let cos2_theta = wm.z() * wm.z();
+        let e = (wm.x() / self.alpha_x).powi(2) + (wm.y() / self.alpha_y).powi(2) + cos2_theta;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    //Smith 遮蔽函数中的 Λ(w)
+    fn lambda(&self, w: Vec3) -> f64 {
+        let cos2_theta = w.z() * w.z();
+        let alpha2_tan2_theta =
+            ((w.x() * self.alpha_x).powi(2) + (w.y() * self.alpha_y).powi(2)) / cos2_theta;
+        ((1.0 + alpha2_tan2_theta).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    //高度相关的遮蔽-阴影函数
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    //从 w 方向可见的微表面法线的分布 D_w(wm)
+    pub fn pdf(&self, w: Vec3, wm: Vec3) -> f64 {
+        if w.z() == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / w.z().abs() * self.d(wm) * dot(&w, &wm).abs()
+    }
+
+    //按可见法线分布采样微表面法线（Heitz 2018），w 在上半球
+    pub fn sample_wm(&self, w: Vec3, (u1, u2): (f64, f64)) -> Vec3 {
+        //拉伸到 alpha = 1 的半球上
+        let wh = unit_vector(&Vec3::new(
+            self.alpha_x * w.x(),
+            self.alpha_y * w.y(),
+            w.z(),
+        ));
+        let wh = if wh.z() < 0.0 { -wh } else { wh };
+        let t1 = if wh.z() < 0.99999 {
+            unit_vector(&cross(&Vec3::new(0.0, 0.0, 1.0), &wh))
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = cross(&wh, &t1);
+
+        //在单位圆盘上均匀采样，再把下半部分压缩到可见的投影区域
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let px = r * phi.cos();
+        let py = r * phi.sin();
+        let h = (1.0 - px * px).sqrt();
+        let s = (1.0 + wh.z()) / 2.0;
+        let py = (1.0 - s) * h + s * py;
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+        let nh = px * t1 + py * t2 + pz * wh;
+
+        unit_vector(&Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        ))
+    }
+}
+
+//(1 - cosθ)^5
+pub fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+//电介质的菲涅尔反射率，eta 为折射一侧与入射一侧折射率之比
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let (cos_theta_i, eta) = if cos_theta_i < 0.0 {
+        (-cos_theta_i, 1.0 / eta)
+    } else {
+        (cos_theta_i, eta)
+    };
+    let cos_theta_i = cos_theta_i.min(1.0);
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return 1.0; //全反射
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
+
+//导体的菲涅尔反射率，eta + i·k 为相对外侧介质的复折射率
+pub fn fresnel_conductor(cos_theta_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2_theta = cos_theta_i.clamp(0.0, 1.0).powi(2);
+    let sin2_theta = 1.0 - cos2_theta;
+    let (eta2, k2) = (eta * eta, k * k);
+    let t0 = eta2 - k2 - sin2_theta;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos2_theta;
+    let t2 = 2.0 * cos2_theta.sqrt() * a;
+    let r_s = (t1 - t2) / (t1 + t2);
+    let t3 = cos2_theta * a2_plus_b2 + sin2_theta * sin2_theta;
+    let t4 = t2 * sin2_theta;
+    let r_p = r_s * (t3 - t4) / (t3 + t4);
+    (r_s + r_p) / 2.0
+}
+
+//w 指向表面外，关于 n 镜面反射
+pub fn reflect(w: Vec3, n: Vec3) -> Vec3 {
+    2.0 * dot(&w, &n) * n - w
+}
+
+//w 指向表面外且与 n 同侧，全反射时返回 None
+pub fn refract(w: Vec3, n: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_theta_i = dot(&n, &w);
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i).max(0.0) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-w / eta + (cos_theta_i / eta - cos_theta_t) * n)
+}
+
+//粗糙的电介质界面，反射和折射共用一个法线分布。wo 在上半球，
+//eta 为下侧与 wo 一侧的折射率之比
+#[derive(Clone, Copy)]
+pub struct MicrofacetDielectric {
+    pub distribution: TrowbridgeReitz,
+    pub eta: f64,
+}
+
+impl MicrofacetDielectric {
+    pub fn new(distribution: TrowbridgeReitz, eta: f64) -> Self {
+        Self { distribution, eta }
+    }
+
+    //折射时的微表面法线，要求 wo 和 wi 分别位于它的两侧
+    fn refraction_half_vector(&self, wo: Vec3, wi: Vec3) -> Option<(Vec3, f64, f64)> {
+        let wm = wo + self.eta * wi;
+        if wm.near_zero() {
+            return None;
+        }
+        let wm = unit_vector(&wm);
+        let wm = if wm.z() < 0.0 { -wm } else { wm };
+        let (dot_o, dot_i) = (dot(&wo, &wm), dot(&wi, &wm));
+        if dot_o <= 0.0 || dot_i >= 0.0 {
+            return None;
+        }
+        Some((wm, dot_o, dot_i))
+    }
+
+    //BSDF 乘以 |cosθi|：wi 在上半球时为反射，在下半球时为折射。
+    //折射按辐射度传输，折射率之比的平方和雅可比中的 eta² 抵消
+    pub fn f(&self, wo: Vec3, wi: Vec3) -> f64 {
+        let (cos_o, cos_i) = (wo.z(), wi.z());
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return 0.0;
+        }
+        if cos_i > 0.0 {
+            let wm = wo + wi;
+            if wm.near_zero() {
+                return 0.0;
+            }
+            let wm = unit_vector(&wm);
+            let fresnel = fresnel_dielectric(dot(&wo, &wm), self.eta);
+            return self.distribution.d(wm) * self.distribution.g(wo, wi) * fresnel / (4.0 * cos_o);
+        }
+        let Some((wm, dot_o, dot_i)) = self.refraction_half_vector(wo, wi) else {
+            return 0.0;
+        };
+        let denom = dot_o + self.eta * dot_i;
+        let fresnel = fresnel_dielectric(dot_o, self.eta);
+        (1.0 - fresnel)
+            * self.distribution.d(wm)
+            * self.distribution.g(wo, wi)
+            * (dot_i * dot_o).abs()
+            / (cos_o * denom * denom)
+    }
+
+    //u 决定反射还是折射，u2 用于采样微表面法线
+    pub fn sample(&self, wo: Vec3, u: f64, u2: (f64, f64)) -> Vec3 {
+        let wm = self.distribution.sample_wm(wo, u2);
+        let fresnel = fresnel_dielectric(dot(&wo, &wm), self.eta);
+        if u < fresnel {
+            reflect(wo, wm)
+        } else {
+            refract(wo, wm, self.eta).unwrap_or_else(|| reflect(wo, wm))
+        }
+    }
+
+    //生成 wi 的概率密度。反射可能落到表面以下，折射也可能回到表面以上，
+    //这里按实际的生成方式累加，f 在这些方向上为 0，估计仍然无偏
+    pub fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        let mut pdf = 0.0;
+        let wm = wo + wi;
+        if !wm.near_zero() {
+            let wm = unit_vector(&wm);
+            let dot_o = dot(&wo, &wm);
+            let fresnel = fresnel_dielectric(dot_o, self.eta);
+            //反射时 dwm/dwi = 1 / (4 |wo·wm|)
+            pdf += fresnel * self.distribution.pdf(wo, wm) / (4.0 * dot_o);
+        }
+        if let Some((wm, dot_o, dot_i)) = self.refraction_half_vector(wo, wi) {
+            let denom = dot_o + self.eta * dot_i;
+            let jacobian = self.eta * self.eta * dot_i.abs() / (denom * denom);
+            let fresnel = fresnel_dielectric(dot_o, self.eta);
+            pdf += (1.0 - fresnel) * self.distribution.pdf(wo, wm) * jacobian;
+        }
+        pdf
+    }
+}
@@ -3,22 +3,23 @@ use crate::hit_checker::{HitRecord, Hittable, HittableList, degrees_to_radians};
 use crate::interval::Interval;
 use crate::material::{Isotropic, Material};
 use crate::onb::ONB;
-use crate::random::{random_double, random_double_range, random_to_sphere};
+use crate::random::{random_2d, random_double_range, random_to_sphere, random_unit_vector};
 use crate::ray::Ray;
 use crate::texture::{SolidColor, Texture};
+use crate::tonemap::luminance;
 use crate::vec3::{Point3, Vec3, cross, dot, unit_vector};
 use crate::vec3color::Color;
 use std::f64::consts::PI;
 use std::sync::Arc;
 
-pub struct Sphere<M: Material> {
+pub struct Sphere<M: Material + ?Sized> {
     center: Ray,
     radius: f64,
     mat: Arc<M>,
     bbox: Aabb,
 }
 
-impl<M: Material> Sphere<M> {
+impl<M: Material + ?Sized> Sphere<M> {
     pub fn new(static_center: Point3, radius: f64, mat: Arc<M>) -> Self {
         let r_vec = Vec3::new(radius, radius, radius);
         Self {
@@ -41,6 +42,10 @@ impl<M: Material> Sphere<M> {
             bbox: Aabb::from_box(box1, box2),
         }
     }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
 }
 
 pub fn get_sphere_uv(p: &Vec3) -> (f64, f64) {
@@ -51,7 +56,7 @@ pub fn get_sphere_uv(p: &Vec3) -> (f64, f64) {
     (u, v)
 }
 
-impl<M: Material + Send + Sync + 'static> Hittable for Sphere<M> {
+impl<M: Material + ?Sized + 'static> Hittable for Sphere<M> {
     fn hit(&self, ray: &Ray, interval: Interval, hit_record: &mut HitRecord) -> bool {
         let current_center = self.center.at(ray.time());
         let oc = current_center - *ray.origin();
@@ -77,8 +82,7 @@ impl<M: Material + Send + Sync + 'static> Hittable for Sphere<M> {
         let outward_normal = (hit_record.pos - current_center) / self.radius;
         hit_record.set_face_normal(ray, outward_normal);
         (hit_record.u, hit_record.v) = get_sphere_uv(&outward_normal);
-        hit_record.normal = outward_normal;
-        hit_record.mat = self.mat.clone();
+        hit_record.mat = self.mat.clone().into_dyn();
         true
     }
 
@@ -94,6 +98,12 @@ impl<M: Material + Send + Sync + 'static> Hittable for Sphere<M> {
         }
 
         let dist_squared = (self.center.at(0.0) - origin).length_squared();
+        if dist_squared <= self.radius * self.radius {
+            //从球内看，按面积均匀采样换算到立体角
+            let distance_squared = rec.t * rec.t * direction.length_squared();
+            let cosine = (dot(&direction, &rec.normal) / direction.length()).abs();
+            return distance_squared / (cosine * self.area());
+        }
         let cos_theta_max = (1.0 - self.radius * self.radius / dist_squared).sqrt();
         let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
 
@@ -103,12 +113,19 @@ impl<M: Material + Send + Sync + 'static> Hittable for Sphere<M> {
     fn random(&self, origin: Point3) -> Vec3 {
         let direction = self.center.at(0.0) - origin;
         let distance_squared = direction.length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return self.center.at(0.0) + self.radius * random_unit_vector() - origin;
+        }
         let uvw = ONB::new(&direction);
         uvw.transform(random_to_sphere(self.radius, distance_squared))
     }
+
+    fn power(&self) -> f64 {
+        PI * self.area() * luminance(&self.mat.average_emission())
+    }
 }
 
-pub struct Quad<M: Material> {
+pub struct Quad<M: Material + ?Sized> {
     q: Point3,
     u: Vec3,
     v: Vec3,
@@ -120,7 +137,7 @@ pub struct Quad<M: Material> {
     area: f64,
 }
 
-impl<M: Material> Quad<M> {
+impl<M: Material + ?Sized> Quad<M> {
     pub fn new(q: Point3, u: Vec3, v: Vec3, mat: Arc<M>) -> Self {
         let n = cross(&u, &v);
         let normal = unit_vector(&n);
@@ -158,7 +175,7 @@ impl<M: Material> Quad<M> {
     }
 }
 
-impl<M: Material + Send + Sync + 'static> Hittable for Quad<M> {
+impl<M: Material + ?Sized + 'static> Hittable for Quad<M> {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
         let denom = dot(&self.normal, r.direction());
         if denom.abs() < 1e-8 {
@@ -177,7 +194,7 @@ impl<M: Material + Send + Sync + 'static> Hittable for Quad<M> {
         }
         rec.t = t;
         rec.pos = intersection;
-        rec.mat = self.mat.clone();
+        rec.mat = self.mat.clone().into_dyn();
         rec.set_face_normal(r, self.normal);
         true
     }
@@ -201,12 +218,17 @@ impl<M: Material + Send + Sync + 'static> Hittable for Quad<M> {
     }
 
     fn random(&self, origin: Vec3) -> Vec3 {
-        let p = self.q + (random_double() * self.u) + (random_double() * self.v);
+        let (r1, r2) = random_2d();
+        let p = self.q + (r1 * self.u) + (r2 * self.v);
         p - origin
     }
+
+    fn power(&self) -> f64 {
+        PI * self.area * luminance(&self.mat.average_emission())
+    }
 }
 
-pub fn make_box<M: Material + Send + Sync + 'static>(
+pub fn make_box<M: Material + ?Sized + 'static>(
     a: Point3,
     b: Point3,
     mat: Arc<M>,
@@ -260,13 +282,13 @@ pub fn make_box<M: Material + Send + Sync + 'static>(
     Arc::new(sides)
 }
 
-pub struct Translate<H: Hittable + Send + Sync + 'static> {
+pub struct Translate<H: Hittable + ?Sized + 'static> {
     object: Arc<H>,
     offset: Vec3,
     bbox: Aabb,
 }
 
-impl<H: Hittable + Send + Sync + 'static> Translate<H> {
+impl<H: Hittable + ?Sized + 'static> Translate<H> {
     pub fn new(object: Arc<H>, offset: Vec3) -> Self {
         let bbox = object.bounding_box() + offset;
         Self {
@@ -277,7 +299,7 @@ impl<H: Hittable + Send + Sync + 'static> Translate<H> {
     }
 }
 
-impl<H: Hittable + Send + Sync + 'static> Hittable for Translate<H> {
+impl<H: Hittable + ?Sized + 'static> Hittable for Translate<H> {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
         let moved_r = Ray::new_with_time(*r.origin() - self.offset, *r.direction(), r.time());
         if !self.object.hit(&moved_r, ray_t, rec) {
@@ -290,16 +312,36 @@ impl<H: Hittable + Send + Sync + 'static> Hittable for Translate<H> {
     fn bounding_box(&self) -> Aabb {
         self.bbox
     }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.object.pdf_value(origin - self.offset, direction)
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        self.object.random(origin - self.offset)
+    }
+
+    fn power(&self) -> f64 {
+        self.object.power()
+    }
+
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Hittable>>) {
+        let mut inner = Vec::new();
+        self.object.collect_emitters(&mut inner);
+        for light in inner {
+            lights.push(Arc::new(Translate::new(light, self.offset)));
+        }
+    }
 }
 
-pub struct RotateY<H: Hittable + Send + Sync + 'static> {
+pub struct RotateY<H: Hittable + ?Sized + 'static> {
     object: Arc<H>,
     sin_theta: f64,
     cos_theta: f64,
     bbox: Aabb,
 }
 
-impl<H: Hittable + Send + Sync + 'static> RotateY<H> {
+impl<H: Hittable + ?Sized + 'static> RotateY<H> {
     pub fn new(object: Arc<H>, angle: f64) -> Self {
         let radians = degrees_to_radians(angle);
         let sin_theta = radians.sin();
@@ -334,9 +376,26 @@ impl<H: Hittable + Send + Sync + 'static> RotateY<H> {
             bbox,
         }
     }
+
+    //世界空间到物体空间
+    fn to_object(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            (self.cos_theta * v.x()) - (self.sin_theta * v.z()),
+            v.y(),
+            (self.sin_theta * v.x()) + (self.cos_theta * v.z()),
+        )
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            (self.cos_theta * v.x()) + (self.sin_theta * v.z()),
+            v.y(),
+            (-self.sin_theta * v.x()) + (self.cos_theta * v.z()),
+        )
+    }
 }
 
-impl<H: Hittable + Send + Sync + 'static> Hittable for RotateY<H> {
+impl<H: Hittable + ?Sized + 'static> Hittable for RotateY<H> {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
         let origin = Point3::new(
             (self.cos_theta * r.origin().x()) - (self.sin_theta * r.origin().z()),
@@ -372,16 +431,58 @@ impl<H: Hittable + Send + Sync + 'static> Hittable for RotateY<H> {
     fn bounding_box(&self) -> Aabb {
         self.bbox
     }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.object
+            .pdf_value(self.to_object(origin), self.to_object(direction))
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        self.to_world(self.object.random(self.to_object(origin)))
+    }
+
+    fn power(&self) -> f64 {
+        self.object.power()
+    }
+
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Hittable>>) {
+        let mut inner = Vec::new();
+        self.object.collect_emitters(&mut inner);
+        for light in inner {
+            let angle = self.sin_theta.atan2(self.cos_theta).to_degrees();
+            lights.push(Arc::new(RotateY::new(light, angle)));
+        }
+    }
 }
 
-pub struct ConstantMedium<H: Hittable + Send + Sync + 'static, M: Material + Send + Sync + 'static>
-{
+//照常渲染和发光，但不加入光源列表，例如包住整个场景的发光背景球
+pub struct ExcludeLight<H: Hittable + ?Sized + 'static> {
+    object: Arc<H>,
+}
+
+impl<H: Hittable + ?Sized + 'static> ExcludeLight<H> {
+    pub fn new(object: Arc<H>) -> Self {
+        Self { object }
+    }
+}
+
+impl<H: Hittable + ?Sized + 'static> Hittable for ExcludeLight<H> {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        self.object.hit(r, ray_t, rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.object.bounding_box()
+    }
+}
+
+pub struct ConstantMedium<H: Hittable + ?Sized + 'static, M: Material + Send + Sync + 'static> {
     boundary: Arc<H>,
     neg_inv_density: f64,
     phase_function: Arc<M>,
 }
 
-impl<H: Hittable + Send + Sync + 'static, T: Texture + Send + Sync + 'static>
+impl<H: Hittable + ?Sized + 'static, T: Texture + ?Sized + 'static>
     ConstantMedium<H, Isotropic<T>>
 {
     pub fn from_texture(boundary: Arc<H>, density: f64, tex: Arc<T>) -> Self {
@@ -395,7 +496,7 @@ impl<H: Hittable + Send + Sync + 'static, T: Texture + Send + Sync + 'static>
 
 impl<H> ConstantMedium<H, Isotropic<SolidColor>>
 where
-    H: Hittable + Send + Sync + 'static,
+    H: Hittable + ?Sized + 'static,
 {
     pub fn from_color(boundary: Arc<H>, density: f64, color: Color) -> Self {
         Self {
@@ -406,7 +507,7 @@ where
     }
 }
 
-impl<H: Hittable + Send + Sync + 'static, M: Material + Send + Sync + 'static> Hittable
+impl<H: Hittable + ?Sized + 'static, M: Material + Send + Sync + 'static> Hittable
     for ConstantMedium<H, M>
 {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
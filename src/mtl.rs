@@ -1,51 +1,181 @@
-use crate::texture::MappedTexture;
+use crate::disney::{Disney, DisneyParams};
+use crate::material::{DiffuseLight, Lambertian, Material};
+use crate::my_image::MyImage;
+use crate::obj_error::ObjError;
+use crate::texture::{Mat3, SolidColor, Texture};
+use crate::tonemap::luminance;
+use crate::vec3::{Point3, Vec3, unit_vector};
+use crate::vec3color::Color;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::sync::Arc;
+
+//贴图语句，路径和 MyImage 一样相对 assets 目录
+#[derive(Clone)]
+pub struct TextureMap {
+    pub path: String,
+    pub scale: [f64; 3],      // -s
+    pub offset: [f64; 3],     // -o
+    pub bump_multiplier: f64, // -bm
+    pub clamp: bool,          // -clamp，否则 uv 循环
+}
+
+impl TextureMap {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            scale: [1.0, 1.0, 1.0],
+            offset: [0.0, 0.0, 0.0],
+            bump_multiplier: 1.0,
+            clamp: false,
+        }
+    }
+}
 
 pub struct MtlInfo {
     pub name: String,
-    pub map_kd: Option<String>,   // 漫反射贴图
-    pub map_bump: Option<String>, // 法线贴图
-    pub map_d: Option<String>,    // Alpha 贴图
+    pub kd: Option<Color>, // 漫反射颜色
+    pub ks: Option<Color>, // 高光颜色
+    pub ke: Option<Color>, // 自发光
+    pub tf: Option<Color>, // 透射滤色
+    pub ns: Option<f64>,   // 高光指数
+    pub ni: Option<f64>,   // 折射率
+    pub d: f64,            // 不透明度，Tr = 1 - d
+    pub illum: Option<u32>,
+    pub pr: Option<f64>,            // PBR 扩展：粗糙度
+    pub pm: Option<f64>,            // PBR 扩展：金属度
+    pub map_kd: Option<TextureMap>, // 漫反射贴图
+    pub map_ks: Option<TextureMap>,
+    pub map_ns: Option<TextureMap>, // 高光指数贴图，乘以 Ns
+    pub map_ke: Option<TextureMap>,
+    pub map_bump: Option<TextureMap>, // 法线贴图
+    pub map_d: Option<TextureMap>,    // Alpha 贴图
+}
+
+impl MtlInfo {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            kd: None,
+            ks: None,
+            ke: None,
+            tf: None,
+            ns: None,
+            ni: None,
+            d: 1.0,
+            illum: None,
+            pr: None,
+            pm: None,
+            map_kd: None,
+            map_ks: None,
+            map_ns: None,
+            map_ke: None,
+            map_bump: None,
+            map_d: None,
+        }
+    }
+
+    //有 Pr 时直接使用，否则由 Ns 换算
+    fn roughness(&self, default: f64) -> f64 {
+        match (self.pr, self.ns) {
+            (Some(pr), _) => pr.clamp(0.0, 1.0),
+            (None, Some(ns)) => ns_to_roughness(ns),
+            (None, None) => default,
+        }
+    }
+
+    //没有 Pr 但有 map_Ns 时逐点换算粗糙度
+    fn roughness_texture(&self, default: f64) -> Arc<dyn Texture> {
+        match (self.pr, &self.map_ns) {
+            (None, Some(map)) => Arc::new(RoughnessMap {
+                ns: self.ns.unwrap_or(1000.0),
+                map: ImageMap::new(map),
+            }),
+            _ => {
+                let roughness = self.roughness(default);
+                Arc::new(SolidColor::new(Color::new(roughness, roughness, roughness)))
+            }
+        }
+    }
 }
 
-pub fn parse_mtl_file(path: &str) -> HashMap<String, MtlInfo> {
-    let file = File::open(path).expect("无法打开MTL文件");
+//按 Blender 导出时的约定 Ns = 1000 (1 - roughness)² 换算
+fn ns_to_roughness(ns: f64) -> f64 {
+    1.0 - (ns / 1000.0).clamp(0.0, 1.0).sqrt()
+}
+
+pub fn parse_mtl_file(path: &str) -> Result<HashMap<String, MtlInfo>, ObjError> {
+    let io_error = |source| ObjError::Io {
+        path: path.to_string(),
+        source,
+    };
+    let file = File::open(path).map_err(io_error)?;
     let reader = BufReader::new(file);
 
     let mut materials = HashMap::new();
-    let mut current = MtlInfo {
-        name: String::new(),
-        map_kd: None,
-        map_bump: None,
-        map_d: None,
-    };
+    let mut current = MtlInfo::new("");
 
-    for line in reader.lines().map_while(Result::ok) {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
+    for (number, line) in reader.lines().enumerate() {
+        let line = line.map_err(io_error)?;
+        //去掉 # 之后的注释
+        let line = line
+            .split_once('#')
+            .map_or(line.as_str(), |(code, _)| code)
+            .trim();
+        if line.is_empty() {
             continue;
         }
 
         let tokens: Vec<&str> = line.split_whitespace().collect();
-        match tokens.first().copied() {
-            Some("newmtl") => {
+        let args = &tokens[1..];
+        let malformed = || ObjError::Parse {
+            path: path.to_string(),
+            line: number + 1,
+            message: format!("malformed '{}' statement", tokens[0]),
+        };
+        match tokens[0].to_ascii_lowercase().as_str() {
+            "newmtl" => {
+                if args.is_empty() {
+                    return Err(malformed());
+                }
                 if !current.name.is_empty() {
                     materials.insert(current.name.clone(), current);
                 }
-                current = MtlInfo {
-                    name: tokens[1].to_string(),
-                    map_kd: None,
-                    map_bump: None,
-                    map_d: None,
-                };
+                current = MtlInfo::new(&args.join(" "));
             }
-            Some("map_Kd") => current.map_kd = tokens.get(1).map(|s| s.to_string()),
-            Some("map_bump") | Some("bump") => {
-                current.map_bump = tokens.get(1).map(|s| s.to_string())
+            //spectral 颜色引用外部的 .rfl 文件，不支持
+            "kd" | "ks" | "ke" | "tf" if args.first() == Some(&"spectral") => {
+                eprintln!(
+                    "warning: {}:{}: spectral colors are not supported, ignoring '{}'",
+                    path,
+                    number + 1,
+                    tokens[0]
+                );
             }
-            Some("map_d") => current.map_d = tokens.get(1).map(|s| s.to_string()),
+            "kd" => current.kd = Some(parse_color(args).ok_or_else(malformed)?),
+            "ks" => current.ks = Some(parse_color(args).ok_or_else(malformed)?),
+            "ke" => current.ke = Some(parse_color(args).ok_or_else(malformed)?),
+            "tf" => current.tf = Some(parse_color(args).ok_or_else(malformed)?),
+            "ns" => current.ns = Some(parse_scalar(args).ok_or_else(malformed)?),
+            "ni" => current.ni = Some(parse_scalar(args).ok_or_else(malformed)?),
+            //d 前面可能带 -halo
+            "d" => current.d = parse_scalar(args).ok_or_else(malformed)?.clamp(0.0, 1.0),
+            "tr" => current.d = 1.0 - parse_scalar(args).ok_or_else(malformed)?.clamp(0.0, 1.0),
+            "illum" => {
+                let illum = args.first().and_then(|s| s.parse().ok());
+                current.illum = Some(illum.ok_or_else(malformed)?);
+            }
+            "pr" => current.pr = Some(parse_scalar(args).ok_or_else(malformed)?),
+            "pm" => current.pm = Some(parse_scalar(args).ok_or_else(malformed)?),
+            "map_kd" => current.map_kd = Some(parse_texture_map(args).ok_or_else(malformed)?),
+            "map_ks" => current.map_ks = Some(parse_texture_map(args).ok_or_else(malformed)?),
+            "map_ns" => current.map_ns = Some(parse_texture_map(args).ok_or_else(malformed)?),
+            "map_ke" => current.map_ke = Some(parse_texture_map(args).ok_or_else(malformed)?),
+            "map_bump" | "bump" | "norm" => {
+                current.map_bump = Some(parse_texture_map(args).ok_or_else(malformed)?)
+            }
+            "map_d" => current.map_d = Some(parse_texture_map(args).ok_or_else(malformed)?),
             _ => {}
         }
     }
@@ -54,13 +184,289 @@ pub fn parse_mtl_file(path: &str) -> HashMap<String, MtlInfo> {
         materials.insert(current.name.clone(), current);
     }
 
-    materials
+    Ok(materials)
+}
+
+fn parse_scalar(args: &[&str]) -> Option<f64> {
+    args.last().and_then(|s| s.parse().ok())
+}
+
+//"Kd r g b"，只给一个数时为灰色；"xyz" 形式近似按 rgb 读取
+fn parse_color(args: &[&str]) -> Option<Color> {
+    let args = match args.first() {
+        Some(&"xyz") => &args[1..],
+        _ => args,
+    };
+    let values: Vec<f64> = args.iter().map(|s| s.parse().ok()).collect::<Option<_>>()?;
+    match values[..] {
+        [v] => Some(Color::new(v, v, v)),
+        [r, g, b, ..] => Some(Color::new(r, g, b)),
+        _ => None,
+    }
+}
+
+//解析贴图语句的选项，剩下的部分是路径（可能含空格）
+fn parse_texture_map(args: &[&str]) -> Option<TextureMap> {
+    let mut map = TextureMap::new("");
+    let mut i = 0;
+    while i < args.len() && args[i].starts_with('-') {
+        let option = args[i];
+        i += 1;
+        //-o、-s、-t 后面有 1 到 3 个数
+        let mut vector = |default: f64| {
+            let mut value = [default; 3];
+            for slot in &mut value {
+                match args.get(i).and_then(|s| s.parse::<f64>().ok()) {
+                    Some(v) => {
+                        *slot = v;
+                        i += 1;
+                    }
+                    None => break,
+                }
+            }
+            value
+        };
+        match option {
+            "-s" => map.scale = vector(1.0),
+            "-o" => map.offset = vector(0.0),
+            "-t" => {
+                vector(0.0);
+            }
+            "-mm" => i += 2,
+            "-bm" => {
+                map.bump_multiplier = args.get(i).and_then(|s| s.parse().ok()).unwrap_or(1.0);
+                i += 1;
+            }
+            "-clamp" => {
+                map.clamp = args.get(i) == Some(&"on");
+                i += 1;
+            }
+            //-blendu、-blendv、-boost、-cc、-texres、-imfchan、-type 都只有一个参数
+            _ => i += 1,
+        }
+    }
+    let path = args.get(i..)?.join(" ");
+    if path.is_empty() {
+        return None;
+    }
+    map.path = path;
+    Some(map)
+}
+
+struct ImageMap {
+    image: MyImage,
+    options: TextureMap,
+}
+
+impl ImageMap {
+    fn new(options: &TextureMap) -> Self {
+        Self {
+            image: MyImage::new(&options.path),
+            options: options.clone(),
+        }
+    }
+
+    fn index(&self, i: f64, size: usize) -> usize {
+        let i = i as isize;
+        if self.options.clamp {
+            i.clamp(0, size as isize - 1) as usize
+        } else {
+            i.rem_euclid(size as isize) as usize
+        }
+    }
+
+    //按 -s、-o 变换 uv 后双线性插值，返回 0~1 的 rgba，图片没有加载时为 None
+    fn sample(&self, u: f64, v: f64) -> Option<[f64; 4]> {
+        let (width, height) = (self.image.width(), self.image.height());
+        if height == 0 {
+            return None;
+        }
+        let u = u * self.options.scale[0] + self.options.offset[0];
+        let v = v * self.options.scale[1] + self.options.offset[1];
+        let (u, v) = if self.options.clamp {
+            (u.clamp(0.0, 1.0), v.clamp(0.0, 1.0))
+        } else {
+            (u - u.floor(), v - v.floor())
+        };
+
+        let x = u * width as f64;
+        let y = (1.0 - v) * height as f64;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (sx, sy) = (x - x0, y - y0);
+        let (i1, i2) = (self.index(x0, width), self.index(x0 + 1.0, width));
+        let (j1, j2) = (self.index(y0, height), self.index(y0 + 1.0, height));
+
+        let pixel1 = self.image.pixel_rgba(i1, j1);
+        let pixel2 = self.image.pixel_rgba(i2, j1);
+        let pixel3 = self.image.pixel_rgba(i1, j2);
+        let pixel4 = self.image.pixel_rgba(i2, j2);
+        Some(std::array::from_fn(|c| {
+            (((1.0 - sx) * pixel1[c] as f64 + sx * pixel2[c] as f64) * (1.0 - sy)
+                + ((1.0 - sx) * pixel3[c] as f64 + sx * pixel4[c] as f64) * sy)
+                / 255.0
+        }))
+    }
+}
+
+//常数颜色乘以贴图，另外带上法线贴图和镂空用的不透明度
+pub struct MtlTexture {
+    factor: Color,
+    map: Option<ImageMap>,
+    bump: Option<ImageMap>,
+    dissolve: f64,
+    alpha_map: Option<ImageMap>,
+}
+
+impl MtlTexture {
+    fn new(factor: Color, map: Option<&TextureMap>) -> Self {
+        Self {
+            factor,
+            map: map.map(ImageMap::new),
+            bump: None,
+            dissolve: 1.0,
+            alpha_map: None,
+        }
+    }
+}
+
+impl Texture for MtlTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        let Some(map) = &self.map else {
+            return self.factor;
+        };
+        match map.sample(u, v) {
+            Some(pixel) => {
+                let color = Color::new(pixel[0], pixel[1], pixel[2]);
+                self.factor * color * color
+            }
+            None => Color::new(0.0, 1.0, 1.0),
+        }
+    }
+
+    fn normal(&self, u: f64, v: f64, normal: Vec3, tangent: Vec3, bitangent: Vec3) -> Option<Vec3> {
+        let bump = self.bump.as_ref()?;
+        let pixel = bump.sample(u, v)?;
+        let strength = bump.options.bump_multiplier;
+        let tangent_space_normal = Vec3::new(
+            strength * (2.0 * pixel[0] - 1.0),
+            strength * (2.0 * pixel[1] - 1.0),
+            2.0 * pixel[2] - 1.0,
+        );
+        let tbn = Mat3::from_cols(tangent, bitangent, normal);
+        Some(unit_vector(&tbn.mul_vec3(tangent_space_normal)))
+    }
+
+    //不透明度。沿用原来的约定，map_d 贴图亮度开方后是被剔除的概率
+    fn alpha(&self, u: f64, v: f64) -> Option<f64> {
+        if self.dissolve >= 1.0 && self.alpha_map.is_none() {
+            return None;
+        }
+        let transparency = self
+            .alpha_map
+            .as_ref()
+            .and_then(|map| map.sample(u, v))
+            .map_or(0.0, |pixel| pixel[0].sqrt());
+        Some(self.dissolve * (1.0 - transparency))
+    }
+}
+
+//map_Ns 的灰度乘以 Ns 得到高光指数，再换算成粗糙度
+struct RoughnessMap {
+    ns: f64,
+    map: ImageMap,
 }
 
-pub fn make_mapped_texture_from_mtl(material: &MtlInfo) -> MappedTexture {
-    MappedTexture::new(
-        material.map_kd.as_deref().unwrap_or("default_diffuse.png"),
-        material.map_bump.as_deref(),
-        material.map_d.as_deref(),
-    )
+impl Texture for RoughnessMap {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        let ns = self
+            .map
+            .sample(u, v)
+            .map_or(self.ns, |pixel| self.ns * pixel[0]);
+        let roughness = ns_to_roughness(ns);
+        Color::new(roughness, roughness, roughness)
+    }
+}
+
+//按 illum 选择材质：0、1 漫反射，2 带高光，3、5、8 镜面，4、6、7、9 玻璃；
+//有自发光时不论 illum 都作为光源。d 和 map_d 由漫反射（镜面时为高光）纹理提供镂空。
+//不少导出器对不透明物体也写 illum 4，所以只有 d < 1、Tf 不是白色或 Ni > 1 时才当作玻璃
+pub fn make_material_from_mtl(info: &MtlInfo) -> Arc<dyn Material> {
+    let white = Color::new(1.0, 1.0, 1.0);
+    let black = Color::new(0.0, 0.0, 0.0);
+    let or_default = |color: Option<Color>, map: &Option<TextureMap>, default: Color| {
+        color.unwrap_or(if map.is_some() { white } else { default })
+    };
+
+    let emission = or_default(info.ke, &info.map_ke, black);
+    if emission.x().max(emission.y()).max(emission.z()) > 0.0 {
+        let tex = Arc::new(MtlTexture::new(emission, info.map_ke.as_ref()));
+        return Arc::new(DiffuseLight::from_texture(tex));
+    }
+
+    let surface = |factor: Color, map: Option<&TextureMap>, dissolve: f64| {
+        let mut tex = MtlTexture::new(factor, map);
+        tex.bump = info.map_bump.as_ref().map(ImageMap::new);
+        tex.dissolve = dissolve;
+        tex.alpha_map = info.map_d.as_ref().map(ImageMap::new);
+        Arc::new(tex)
+    };
+    let constant = |value: f64| -> Arc<dyn Texture> {
+        Arc::new(SolidColor::new(Color::new(value, value, value)))
+    };
+
+    //有贴图时 Kd 0 表示只用贴图颜色，而不是黑色
+    let kd = match (info.kd, &info.map_kd) {
+        (Some(kd), Some(_)) if kd.x().max(kd.y()).max(kd.z()) <= 0.0 => white,
+        (kd, map) => or_default(kd, map, Color::new(0.8, 0.8, 0.8)),
+    };
+    let diffuse = surface(kd, info.map_kd.as_ref(), info.d);
+    let specular = or_default(info.ks, &info.map_ks, black);
+    let illum = info
+        .illum
+        .unwrap_or(if luminance(&specular) > 0.0 { 2 } else { 1 });
+    let transmissive = info.d < 1.0
+        || info
+            .tf
+            .is_some_and(|tf| tf.x().min(tf.y()).min(tf.z()) < 1.0)
+        || info.ni.is_some_and(|ni| ni > 1.0);
+
+    match illum {
+        2 if luminance(&specular) > 0.0 || info.pm.is_some() => {
+            //Ks 为 0.5 时对应常见电介质 F0 = 0.04，和 Blender 的默认值一致
+            let params = DisneyParams {
+                base_color: diffuse,
+                metallic: constant(info.pm.unwrap_or(0.0)),
+                roughness: info.roughness_texture(0.5),
+                specular: constant(luminance(&specular)),
+                ..Default::default()
+            };
+            Arc::new(Disney::new(params))
+        }
+        3 | 5 | 8 => {
+            let reflectance = surface(
+                or_default(info.ks, &info.map_ks, white),
+                info.map_ks.as_ref(),
+                info.d,
+            );
+            let params = DisneyParams {
+                base_color: reflectance,
+                metallic: constant(1.0),
+                roughness: info.roughness_texture(0.0),
+                ..Default::default()
+            };
+            Arc::new(Disney::new(params))
+        }
+        4 | 6 | 7 | 9 if transmissive => {
+            //Tf 是透射光的颜色；d 已经体现在透射里，不再用来镂空
+            let params = DisneyParams {
+                base_color: surface(info.tf.unwrap_or(white), None, 1.0),
+                roughness: info.roughness_texture(0.0),
+                transmission: constant(1.0),
+                ior: constant(info.ni.filter(|&ni| ni > 1.0).unwrap_or(1.5)),
+                ..Default::default()
+            };
+            Arc::new(Disney::new(params))
+        }
+        _ => Arc::new(Lambertian::from_tex(diffuse)),
+    }
 }
@@ -1,294 +1,329 @@
-use crate::aabb::Aabb;
 use crate::bvh::BvhNode;
-use crate::hit_checker::{HitRecord, Hittable, HittableList};
-use crate::interval::Interval;
-use crate::material::Lambertian;
+use crate::hit_checker::HittableList;
+use crate::material::Material;
+use crate::mesh::TriangleMesh;
 use crate::modeling::{RotateY, Translate};
-use crate::mtl::{make_mapped_texture_from_mtl, parse_mtl_file};
-use crate::random::random_double;
-use crate::ray::Ray;
-use crate::texture::{MappedTexture, Texture};
+use crate::mtl::{MtlInfo, TextureMap, make_material_from_mtl, parse_mtl_file};
+pub use crate::obj_error::ObjError;
 use crate::uv::UV;
-use crate::vec3::{Point3, Vec3, cross, dot, unit_vector};
-use std::collections::HashMap;
+use crate::vec3::{Point3, Vec3};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use std::sync::Arc;
-use tobj::{LoadOptions, load_obj};
 
-pub fn interpolate_normals(n0: Vec3, n1: Vec3, n2: Vec3, u: f64, v: f64) -> Vec3 {
-    let w = 1.0 - u - v;
-    let interpolated = n0 * w + n1 * u + n2 * v;
-    unit_vector(&interpolated)
+//面顶点中缺省的 uv 或法线下标
+const MISSING: u32 = u32::MAX;
+
+//连续的、使用同一材质的面，顶点按 (位置, uv, 法线) 的下标组合去重
+struct MeshBuilder {
+    material: Option<String>,
+    vertices: HashMap<[u32; 3], u32>,
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<UV>,
+    indices: Vec<[u32; 3]>,
+    missing_normals: bool,
+    missing_uvs: bool,
 }
 
-pub struct Triangle<T: Texture> {
-    p0: Point3, //顶点0
-    p1: Point3,
-    p2: Point3,
-    pub uv0: UV, //顶点0的uv坐标
-    pub uv1: UV,
-    pub uv2: UV,
-    n0: Vec3, //顶点0的点法线
-    n1: Vec3,
-    n2: Vec3,
-    e1: Vec3, //边01
-    e2: Vec3, //边02
-    tangent: Vec3,
-    mat: Arc<Lambertian<T>>,
-    bbox: Aabb,
-}
-
-impl<T: Texture> Triangle<T> {
-    pub fn new(
-        (p0, p1, p2): (Point3, Point3, Point3),
-        (uv0, uv1, uv2): (UV, UV, UV),
-        (n0, n1, n2): (Vec3, Vec3, Vec3),
-        mat: Arc<Lambertian<T>>,
-    ) -> Self {
-        let e1 = p1 - p0;
-        let e2 = p2 - p0;
-        let delta_uv1 = uv1 - uv0;
-        let delta_uv2 = uv2 - uv0;
-
-        let r = 1.0 / (delta_uv1.u() * delta_uv2.v() - delta_uv1.v() * delta_uv2.u());
-        let tangent = (e1 * delta_uv2.v() - e2 * delta_uv1.v()) * r;
-
-        let mut triangle = Self {
-            p0,
-            p1,
-            p2,
-            uv0,
-            uv1,
-            uv2,
-            n0,
-            n1,
-            n2,
-            e1,
-            e2,
-            tangent,
-            mat,
-            bbox: Aabb::default(),
-        };
-        triangle.set_bounding_box();
-        triangle
+impl MeshBuilder {
+    fn new(material: Option<String>) -> Self {
+        Self {
+            material,
+            vertices: HashMap::new(),
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: Vec::new(),
+            missing_normals: false,
+            missing_uvs: false,
+        }
     }
 
-    fn set_bounding_box(&mut self) {
-        let min_x = self.p0.x().min(self.p1.x()).min(self.p2.x());
-        let min_y = self.p0.y().min(self.p1.y()).min(self.p2.y());
-        let min_z = self.p0.z().min(self.p1.z()).min(self.p2.z());
-        let max_x = self.p0.x().max(self.p1.x()).max(self.p2.x());
-        let max_y = self.p0.y().max(self.p1.y()).max(self.p2.y());
-        let max_z = self.p0.z().max(self.p1.z()).max(self.p2.z());
-        self.bbox = Aabb::new(
-            Interval::new(min_x, max_x),
-            Interval::new(min_y, max_y),
-            Interval::new(min_z, max_z),
-        );
-    }
-}
-
-impl<T: Texture + 'static> Hittable for Triangle<T> {
-    fn hit(&self, ray: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
-        // ... 你的 hit 实现逻辑保持不变，只确保 self.mat 是 Arc<Lambertian<T>>
-        let h = cross(ray.direction(), &self.e2);
-        let a = dot(&self.e1, &h);
-        if a.abs() < 1e-8 {
-            return false;
+    fn vertex(&mut self, key: [u32; 3], obj: &ObjData) -> u32 {
+        if let Some(&index) = self.vertices.get(&key) {
+            return index;
         }
-
-        let f = 1.0 / a;
-        let s = *ray.origin() - self.p0;
-        let u = f * dot(&s, &h);
-        if !(0.0..=1.0).contains(&u) {
-            return false;
+        let index = self.positions.len() as u32;
+        self.positions.push(obj.positions[key[0] as usize]);
+        if key[1] == MISSING {
+            self.missing_uvs = true;
+            self.uvs.push(UV::default());
+        } else {
+            self.uvs.push(obj.uvs[key[1] as usize]);
         }
-
-        let q = cross(&s, &self.e1);
-        let v = f * dot(ray.direction(), &q);
-        if v < 0.0 || u + v > 1.0 {
-            return false;
+        if key[2] == MISSING {
+            self.missing_normals = true;
+            self.normals.push(Vec3::default());
+        } else {
+            self.normals.push(obj.normals[key[2] as usize]);
         }
+        self.vertices.insert(key, index);
+        index
+    }
+}
 
-        let t = f * dot(&self.e2, &q);
-        if !ray_t.contains(t) {
-            return false;
-        }
+//整个文件共享的顶点数据
+#[derive(Default)]
+struct ObjData {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<UV>,
+}
 
-        let intersection = ray.at(t);
-        let uv = self.uv0 * (1.0 - u - v) + self.uv1 * u + self.uv2 * v;
-        let normal = interpolate_normals(self.n0, self.n1, self.n2, u, v);
-        let true_normal = unit_vector(&cross(&self.e1, &self.e2));
-        let tangent = unit_vector(&(self.tangent - normal * dot(&self.tangent, &normal)));
-        let bitangent = cross(&normal, &tangent);
+//OBJ 下标从 1 开始，负数表示从当前末尾倒数
+fn resolve_index(token: &str, count: usize) -> Option<u32> {
+    let index: i64 = token.parse().ok()?;
+    let index = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    (0..count as i64).contains(&index).then_some(index as u32)
+}
 
-        let alpha = self.mat.alpha(uv.u(), uv.v());
-        if random_double() <= alpha {
-            return false;
+//按 o、g、usemtl 把面分成若干网格，多边形按扇形三角化
+fn parse_obj_file(path: &str, rate: f64) -> Result<Vec<MeshBuilder>, ObjError> {
+    let io_error = |source| ObjError::Io {
+        path: path.to_string(),
+        source,
+    };
+    let file = File::open(path).map_err(io_error)?;
+    let reader = BufReader::new(file);
+
+    let mut obj = ObjData::default();
+    let mut meshes = Vec::new();
+    let mut current = MeshBuilder::new(None);
+    let mut face = Vec::new();
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line.map_err(io_error)?;
+        //去掉 # 之后的注释
+        let line = line
+            .split_once('#')
+            .map_or(line.as_str(), |(code, _)| code)
+            .trim();
+        if line.is_empty() {
+            continue;
         }
 
-        rec.u = uv.u();
-        rec.v = uv.v();
-        rec.t = t;
-        rec.pos = intersection;
-        rec.mat = self.mat.clone();
-        rec.tangent = tangent;
-        rec.bitangent = bitangent;
-        rec.normal = true_normal;
-        true
-    }
-
-    fn bounding_box(&self) -> Aabb {
-        self.bbox
-    }
-
-    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
-        let mut rec = HitRecord::default();
-        if !self.hit(
-            &Ray::new(origin, direction),
-            Interval::new(0.001, f64::INFINITY),
-            &mut rec,
-        ) {
-            return 0.0;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let args = &tokens[1..];
+        let error = |message: String| ObjError::Parse {
+            path: path.to_string(),
+            line: number + 1,
+            message,
+        };
+        let malformed = || error(format!("malformed '{}' statement", tokens[0]));
+        let numbers = |n: usize| -> Result<Vec<f64>, ObjError> {
+            let values: Vec<f64> = args.iter().map_while(|s| s.parse().ok()).collect();
+            if values.len() < n {
+                return Err(malformed());
+            }
+            Ok(values)
+        };
+        match tokens[0] {
+            "v" => {
+                let p = numbers(3)?;
+                obj.positions
+                    .push(Point3::new(rate * p[0], rate * p[1], rate * p[2]));
+            }
+            "vn" => {
+                let n = numbers(3)?;
+                obj.normals.push(Vec3::new(n[0], n[1], n[2]));
+            }
+            "vt" => {
+                let t = numbers(1)?;
+                obj.uvs
+                    .push(UV::new(t[0], t.get(1).copied().unwrap_or(0.0)));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(error("face has fewer than 3 vertices".to_string()));
+                }
+                face.clear();
+                for vertex in args {
+                    let mut parts = vertex.split('/');
+                    let mut key = [MISSING; 3];
+                    let counts = [obj.positions.len(), obj.uvs.len(), obj.normals.len()];
+                    for (slot, count) in key.iter_mut().zip(counts) {
+                        match parts.next() {
+                            Some("") | None => {}
+                            Some(token) => {
+                                *slot = resolve_index(token, count).ok_or_else(|| {
+                                    error(format!(
+                                        "face vertex '{}': index {} out of range",
+                                        vertex, token
+                                    ))
+                                })?
+                            }
+                        }
+                    }
+                    if key[0] == MISSING || parts.next().is_some() {
+                        return Err(error(format!("malformed face vertex '{}'", vertex)));
+                    }
+                    face.push(current.vertex(key, &obj));
+                }
+                for i in 1..face.len() - 1 {
+                    current.indices.push([face[0], face[i], face[i + 1]]);
+                }
+            }
+            "o" | "g" | "usemtl" => {
+                let material = if tokens[0] == "usemtl" {
+                    if args.is_empty() {
+                        return Err(malformed());
+                    }
+                    Some(args.join(" "))
+                } else {
+                    current.material.clone()
+                };
+                let next = MeshBuilder::new(material);
+                let previous = std::mem::replace(&mut current, next);
+                if !previous.indices.is_empty() {
+                    meshes.push(previous);
+                }
+            }
+            //mtllib、s、l、p 等语句不影响三角形
+            _ => {}
         }
-        let distance_squared = rec.t * rec.t * direction.length_squared();
-        let cosine = (dot(&direction, &rec.normal) / direction.length()).abs();
-        distance_squared / (cosine * cross(&self.e1, &self.e2).length() / 2.0)
     }
 
-    fn random(&self, origin: Vec3) -> Vec3 {
-        let p = self.p0 + (random_double() * self.e1) + (random_double() * self.e2);
-        p - origin
+    if !current.indices.is_empty() {
+        meshes.push(current);
     }
+    Ok(meshes)
 }
 
-pub fn obj_loader(obj_path: &str, mtl_path: &str, rate: f64) -> Vec<Triangle<MappedTexture>> {
-    // 加载 .obj 模型与材质列表
-    let (models, materials) = load_obj(
-        obj_path,
-        &LoadOptions {
-            triangulate: true,
-            single_index: true,
-            ..Default::default()
-        },
-    )
-    .expect("OBJ 加载失败");
+//生成法线时默认的折痕角（度）
+pub const DEFAULT_CREASE_ANGLE: f64 = 60.0;
 
+//每个物体生成一个网格，material 不为空时都使用它，忽略 MTL 中的材质
+pub fn obj_loader(
+    obj_path: &str,
+    mtl_path: &str,
+    rate: f64,
+    material: Option<Arc<dyn Material>>,
+    crease_angle: f64,
+) -> Result<Vec<TriangleMesh<dyn Material>>, ObjError> {
+    //MTL 读不了时只给出警告，整个模型使用默认材质
     let mut material_map = HashMap::new();
-
-    let mtl_path = mtl_path.to_string();
-
-    if let Ok(parsed) = std::panic::catch_unwind(|| parse_mtl_file(&mtl_path)) {
-        for (_, info) in parsed {
-            let tex = Arc::new(make_mapped_texture_from_mtl(&info));
-            let mat = Arc::new(Lambertian::from_tex(tex));
-            material_map.insert(info.name.clone(), mat);
+    let mut mtl_loaded = true;
+    if material.is_none() {
+        match parse_mtl_file(mtl_path) {
+            Ok(infos) => {
+                for (name, info) in infos {
+                    material_map.insert(name, make_material_from_mtl(&info));
+                }
+            }
+            Err(e) => {
+                eprintln!("warning: {}, using the default material", e);
+                mtl_loaded = false;
+            }
         }
     }
-
-    let mut triangles = Vec::new();
-
-    let materials = materials.unwrap();
-    for model in models {
-        let mesh = &model.mesh;
-        let positions = &mesh.positions;
-        let texcoords = &mesh.texcoords;
-        let indices = &mesh.indices;
-        let normals = &mesh.normals;
-
-        let material = if let Some(mat_id) = mesh.material_id {
-            let tobj_material = materials.get(mat_id);
-            if let Some(mat) = tobj_material {
-                if let Some(resolved) = material_map.get(&mat.name) {
-                    resolved.clone()
-                } else {
-                    Arc::new(Lambertian::from_tex(Arc::new(MappedTexture::new(
-                        "default_diffuse.png",
-                        None,
-                        None,
-                    ))))
+    let builders = parse_obj_file(obj_path, rate)?;
+
+    let mut default_material = None;
+    let mut unresolved = HashSet::new();
+    let mut meshes = Vec::new();
+    for mut builder in builders {
+        let resolved = builder
+            .material
+            .as_ref()
+            .and_then(|name| material_map.get(name));
+        let material = match (&material, resolved) {
+            (Some(material), _) => material.clone(),
+            (None, Some(resolved)) => resolved.clone(),
+            (None, None) => {
+                if let (true, Some(name)) = (mtl_loaded, &builder.material) {
+                    if unresolved.insert(name.clone()) {
+                        eprintln!(
+                            "warning: {}: material '{}' not found, using the default material",
+                            obj_path, name
+                        );
+                    }
                 }
-            } else {
-                Arc::new(Lambertian::from_tex(Arc::new(MappedTexture::new(
-                    "default_diffuse.png",
-                    None,
-                    None,
-                ))))
+                default_material
+                    .get_or_insert_with(|| {
+                        let mut info = MtlInfo::new("default");
+                        info.map_kd = Some(TextureMap::new("default_diffuse.png"));
+                        make_material_from_mtl(&info)
+                    })
+                    .clone()
             }
-        } else {
-            Arc::new(Lambertian::from_tex(Arc::new(MappedTexture::new(
-                "default_diffuse.png",
-                None,
-                None,
-            ))))
         };
 
-        for i in (0..indices.len()).step_by(3) {
-            let get_vertex = |j| {
-                let idx = indices[i + j] as usize;
-                Point3::new(
-                    rate * positions[3 * idx] as f64,
-                    rate * positions[3 * idx + 1] as f64,
-                    rate * positions[3 * idx + 2] as f64,
-                )
-            };
-
-            let get_uv = |j| {
-                let idx = indices[i + j] as usize;
-                if texcoords.len() >= 2 * idx + 2 {
-                    UV::new(texcoords[2 * idx] as f64, texcoords[2 * idx + 1] as f64)
-                } else {
-                    UV::default()
-                }
-            };
-
-            let get_normal = |j| {
-                let idx = indices[i + j] as usize;
-                Vec3::new(
-                    rate * normals[3 * idx] as f64,
-                    rate * normals[3 * idx + 1] as f64,
-                    rate * normals[3 * idx + 2] as f64,
-                )
-            };
-
-            let v0 = get_vertex(0);
-            let v1 = get_vertex(1);
-            let v2 = get_vertex(2);
-
-            let uv0 = get_uv(0);
-            let uv1 = get_uv(1);
-            let uv2 = get_uv(2);
-
-            let n0 = get_normal(0);
-            let n1 = get_normal(1);
-            let n2 = get_normal(2);
-
-            triangles.push(Triangle::new(
-                (v0, v1, v2),
-                (uv0, uv1, uv2),
-                (n0, n1, n2),
-                material.clone(),
-            ));
+        //缺少 uv 时 uv 都为 0，有顶点缺少法线时整个网格重新生成法线
+        if builder.missing_uvs {
+            builder.uvs.clear();
         }
+        let mesh = if builder.missing_normals {
+            TriangleMesh::with_smooth_normals(
+                builder.positions,
+                builder.uvs,
+                builder.indices,
+                crease_angle,
+                material,
+            )
+        } else {
+            TriangleMesh::new(
+                builder.positions,
+                builder.normals,
+                builder.uvs,
+                builder.indices,
+                material,
+            )
+        };
+        meshes.push(mesh);
     }
 
-    triangles
+    Ok(meshes)
 }
 
-pub fn create_model(
+pub fn load_model(
     obj_path: &str,
     mtl_path: &str,
-    world: &mut HittableList,
     angle: f64,
     offset: Vec3,
     rate: f64, //放大倍率
-) {
-    let vec = obj_loader(obj_path, mtl_path, rate);
+    material: Option<Arc<dyn Material>>,
+    crease_angle: f64, //没有法线时生成法线的折痕角（度）
+) -> Result<Arc<Translate<RotateY<BvhNode>>>, ObjError> {
+    let meshes = obj_loader(obj_path, mtl_path, rate, material, crease_angle)?;
+    if meshes.is_empty() {
+        return Err(ObjError::Empty {
+            path: obj_path.to_string(),
+        });
+    }
     let mut model = HittableList::default();
-    for triangle in vec {
-        model.add(Arc::new(triangle));
+    for mesh in meshes {
+        model.add(Arc::new(mesh));
     }
     let bvh = BvhNode::from_list(&mut model);
     let model_rotate_y = Arc::new(RotateY::new(Arc::new(bvh), angle));
-    let model_translate = Arc::new(Translate::new(model_rotate_y, offset));
-    world.add(model_translate.clone());
+    Ok(Arc::new(Translate::new(model_rotate_y, offset)))
+}
+
+pub fn create_model(
+    obj_path: &str,
+    mtl_path: &str,
+    world: &mut HittableList,
+    angle: f64,
+    offset: Vec3,
+    rate: f64, //放大倍率
+) {
+    //加载失败时报告错误并跳过这个模型
+    match load_model(
+        obj_path,
+        mtl_path,
+        angle,
+        offset,
+        rate,
+        None,
+        DEFAULT_CREASE_ANGLE,
+    ) {
+        Ok(model) => world.add(model),
+        Err(e) => eprintln!("{}", e),
+    }
 }
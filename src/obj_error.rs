@@ -0,0 +1,41 @@
+use std::fmt;
+
+//OBJ/MTL 加载失败时指出文件、行号和原因
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    Parse {
+        path: String,
+        line: usize,
+        message: String,
+    },
+    Empty {
+        path: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "cannot read '{}': {}", path, source),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path, line, message),
+            ObjError::Empty { path } => write!(f, "'{}' contains no faces", path),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::vec3::{Vec3, cross, unit_vector};
+use crate::vec3::{Vec3, cross, dot, unit_vector};
 
 pub struct ONB {
     axis: [Vec3; 3],
@@ -17,6 +17,18 @@ impl ONB {
         ONB { axis: [u, v, w] }
     }
 
+    //w 为法线，u 尽量贴近给定的切线方向
+    pub fn from_normal_tangent(n: &Vec3, tangent: &Vec3) -> Self {
+        let w = unit_vector(n);
+        let t = *tangent - dot(tangent, &w) * w;
+        if t.near_zero() {
+            return Self::new(n);
+        }
+        let u = unit_vector(&t);
+        let v = cross(&w, &u);
+        ONB { axis: [u, v, w] }
+    }
+
     pub fn u(&self) -> Vec3 {
         self.axis[0]
     }
@@ -30,4 +42,13 @@ impl ONB {
     pub fn transform(&self, v: Vec3) -> Vec3 {
         v[0] * self.axis[0] + v[1] * self.axis[1] + v[2] * self.axis[2]
     }
+
+    //transform 的逆变换
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            dot(&v, &self.axis[0]),
+            dot(&v, &self.axis[1]),
+            dot(&v, &self.axis[2]),
+        )
+    }
 }
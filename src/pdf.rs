@@ -10,6 +10,32 @@ pub trait Pdf {
     fn generate(&self) -> Vec3;
 }
 
+//多重重要性采样中两种采样策略的组合权重
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MisHeuristic {
+    Balance,
+    Power, //指数为 2
+}
+
+impl MisHeuristic {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "balance" => Some(MisHeuristic::Balance),
+            "power" => Some(MisHeuristic::Power),
+            _ => None,
+        }
+    }
+
+    //按 pdf 采样得到的样本的权重，other_pdf 为另一种策略生成同一方向的概率密度
+    pub fn weight(&self, pdf: f64, other_pdf: f64) -> f64 {
+        let (f, g) = match self {
+            MisHeuristic::Balance => (pdf, other_pdf),
+            MisHeuristic::Power => (pdf * pdf, other_pdf * other_pdf),
+        };
+        if f + g > 0.0 { f / (f + g) } else { 0.0 }
+    }
+}
+
 #[derive(Default)]
 pub struct DummyPdf;
 
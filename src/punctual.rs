@@ -0,0 +1,168 @@
+use crate::onb::ONB;
+use crate::random::random_2d;
+use crate::vec3::{Point3, Vec3, dot, unit_vector};
+use crate::vec3color::Color;
+use std::f64::consts::PI;
+
+//几何体击中不到的光源，只能在光源采样（NEE）中计入
+pub trait PunctualLight: Send + Sync {
+    //返回 (指向光源的单位方向, 到光源的距离, 到达 origin 的照度)
+    fn sample(&self, origin: Point3) -> (Vec3, f64, Color);
+
+    //点光源和聚光灯的位置，方向光在无穷远处为 None
+    fn position(&self) -> Option<Point3>;
+
+    //辐射功率的估计，方向光按照亮半径为 scene_radius 的圆盘计算
+    fn power(&self, scene_radius: f64) -> Color;
+}
+
+pub struct PointLight {
+    position: Point3,
+    intensity: Color, //辐射强度，照度为 intensity / 距离²
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl PunctualLight for PointLight {
+    fn sample(&self, origin: Point3) -> (Vec3, f64, Color) {
+        let to_light = self.position - origin;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        (
+            to_light / distance,
+            distance,
+            self.intensity / distance_squared,
+        )
+    }
+
+    fn position(&self) -> Option<Point3> {
+        Some(self.position)
+    }
+
+    fn power(&self, _scene_radius: f64) -> Color {
+        4.0 * PI * self.intensity
+    }
+}
+
+//内锥角以内强度不变，内外锥角之间按 smoothstep 衰减到 0
+pub struct SpotLight {
+    position: Point3,
+    direction: Vec3, //照射方向
+    intensity: Color,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl SpotLight {
+    //角度为半角（度）
+    pub fn new(
+        position: Point3,
+        direction: Vec3,
+        intensity: Color,
+        inner_angle: f64,
+        outer_angle: f64,
+    ) -> Self {
+        let outer = outer_angle.clamp(0.0, 180.0);
+        let inner = inner_angle.clamp(0.0, outer);
+        Self {
+            position,
+            direction: unit_vector(&direction),
+            intensity,
+            cos_inner: inner.to_radians().cos(),
+            cos_outer: outer.to_radians().cos(),
+        }
+    }
+}
+
+//聚光灯的角度衰减，cos_theta 为与照射方向夹角的余弦
+pub fn spot_falloff(cos_theta: f64, cos_inner: f64, cos_outer: f64) -> f64 {
+    if cos_theta >= cos_inner {
+        return 1.0;
+    }
+    if cos_theta <= cos_outer {
+        return 0.0;
+    }
+    let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+    t * t * (3.0 - 2.0 * t)
+}
+
+impl PunctualLight for SpotLight {
+    fn sample(&self, origin: Point3) -> (Vec3, f64, Color) {
+        let to_light = self.position - origin;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        let direction = to_light / distance;
+        let falloff = spot_falloff(
+            dot(&-direction, &self.direction),
+            self.cos_inner,
+            self.cos_outer,
+        );
+        (
+            direction,
+            distance,
+            self.intensity * (falloff / distance_squared),
+        )
+    }
+
+    fn position(&self) -> Option<Point3> {
+        Some(self.position)
+    }
+
+    fn power(&self, _scene_radius: f64) -> Color {
+        //smoothstep 衰减区按一半计入
+        2.0 * PI * (1.0 - (self.cos_inner + self.cos_outer) / 2.0) * self.intensity
+    }
+}
+
+//来自无穷远处的平行光。视直径大于 0 时在对应的视锥内采样方向，得到软阴影，
+//但和其他 punctual 光源一样不会被材质采样的光线看到
+pub struct DirectionalLight {
+    direction: Vec3, //指向光源
+    irradiance: Color,
+    cos_max: f64,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color, angular_diameter: f64) -> Self {
+        let radius = (angular_diameter / 2.0).clamp(0.0, 90.0).to_radians();
+        Self {
+            direction: unit_vector(&direction),
+            irradiance,
+            cos_max: radius.cos(),
+        }
+    }
+}
+
+impl PunctualLight for DirectionalLight {
+    fn sample(&self, _origin: Point3) -> (Vec3, f64, Color) {
+        let direction = if self.cos_max < 1.0 {
+            let (r1, r2) = random_2d();
+            let cos_theta = 1.0 - r1 * (1.0 - self.cos_max);
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * r2;
+            unit_vector(&ONB::new(&self.direction).transform(Vec3::new(
+                sin_theta * phi.cos(),
+                sin_theta * phi.sin(),
+                cos_theta,
+            )))
+        } else {
+            self.direction
+        };
+        (direction, f64::INFINITY, self.irradiance)
+    }
+
+    fn position(&self) -> Option<Point3> {
+        None
+    }
+
+    fn power(&self, scene_radius: f64) -> Color {
+        PI * scene_radius * scene_radius * self.irradiance
+    }
+}
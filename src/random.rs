@@ -1,9 +1,127 @@
-use crate::vec3::{Vec3, dot, unit_vector};
-use rand::{Rng, rng};
+use crate::sampler::{Sampler, hashed_sample};
+use crate::vec3::{Vec3, dot};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng, rng};
+use std::cell::RefCell;
 use std::f64::consts::PI;
+use std::sync::{Arc, OnceLock};
+
+static SEED: OnceLock<u64> = OnceLock::new();
+
+thread_local! {
+    static SEEDED_RNG: RefCell<Option<SmallRng>> = const { RefCell::new(None) };
+    //渲染时当前线程正在计算的样本
+    static SAMPLE_STREAM: RefCell<Option<SampleStream>> = const { RefCell::new(None) };
+}
+
+struct SampleStream {
+    sampler: Arc<dyn Sampler>,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+    end: u32,      //当前这段维度的上界，超出后改用独立随机数
+    overflow: u32, //已经取过的独立随机数个数
+}
+
+impl SampleStream {
+    //取 count 维，返回起始维度；这一段放不下时返回 None
+    fn take(&mut self, count: u32) -> Option<u32> {
+        if self.dimension + count <= self.end {
+            self.dimension += count;
+            Some(self.dimension - count)
+        } else {
+            None
+        }
+    }
+
+    fn independent(&mut self) -> f64 {
+        self.overflow += 1;
+        hashed_sample(
+            seed(),
+            self.pixel,
+            self.index,
+            OVERFLOW_DIMENSION + self.overflow,
+        )
+    }
+}
+
+const OVERFLOW_DIMENSION: u32 = 1 << 30;
+
+//必须在第一次取随机数之前调用，未设置时使用随机种子
+pub fn set_seed(seed: u64) {
+    SEED.set(seed).expect("random seed can only be set once");
+}
+
+pub fn seed() -> u64 {
+    *SEED.get_or_init(|| rng().random())
+}
+
+//在像素 pixel 第 index 个样本的上下文中执行 f，期间的随机数都由 sampler 给出，
+//结果只取决于 (种子, 像素, 样本, 维度)，与线程数和调度顺序无关
+pub fn with_sample_stream<R>(
+    sampler: &Arc<dyn Sampler>,
+    pixel: (u32, u32),
+    index: u32,
+    f: impl FnOnce() -> R,
+) -> R {
+    let stream = SampleStream {
+        sampler: sampler.clone(),
+        pixel,
+        index,
+        dimension: 0,
+        end: u32::MAX,
+        overflow: 0,
+    };
+    let outer = SAMPLE_STREAM.replace(Some(stream));
+    let result = f();
+    SAMPLE_STREAM.set(outer);
+    result
+}
+
+//之后的随机数依次取 [first, first + count) 维，路径每次弹射前调用，
+//这样前面一次弹射多取或少取了几个随机数也不会让后面的维度错位
+pub fn start_dimensions(first: u32, count: u32) {
+    SAMPLE_STREAM.with_borrow_mut(|stream| {
+        if let Some(stream) = stream {
+            stream.dimension = first;
+            stream.end = first.saturating_add(count);
+        }
+    });
+}
 
 pub fn random_double() -> f64 {
-    rng().random_range(0.0..1.0)
+    let sample = SAMPLE_STREAM.with_borrow_mut(|stream| {
+        let stream = stream.as_mut()?;
+        Some(match stream.take(1) {
+            Some(dimension) => stream.sampler.get_1d(stream.pixel, stream.index, dimension),
+            None => stream.independent(),
+        })
+    });
+    if let Some(sample) = sample {
+        return sample;
+    }
+    //场景构建等渲染之外的随机数，每个线程使用由种子和线程编号派生的发生器
+    SEEDED_RNG.with(|cell| {
+        cell.borrow_mut()
+            .get_or_insert_with(|| {
+                //主线程编号为0，rayon 工作线程从1开始
+                let thread = rayon::current_thread_index().map_or(0, |i| i as u64 + 1);
+                SmallRng::seed_from_u64(seed() ^ thread.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+            })
+            .random_range(0.0..1.0)
+    })
+}
+
+//两维联合分层的随机数，用于像素、镜头、光源和 BSDF 上的二维采样
+pub fn random_2d() -> (f64, f64) {
+    let sample = SAMPLE_STREAM.with_borrow_mut(|stream| {
+        let stream = stream.as_mut()?;
+        Some(match stream.take(2) {
+            Some(dimension) => stream.sampler.get_2d(stream.pixel, stream.index, dimension),
+            None => (stream.independent(), stream.independent()),
+        })
+    });
+    sample.unwrap_or_else(|| (random_double(), random_double()))
 }
 
 pub fn random_double_range(min: f64, max: f64) -> f64 {
@@ -31,8 +149,7 @@ pub fn random_range(min: f64, max: f64) -> Vec3 {
 }
 
 pub fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
-    let r1 = random_double();
-    let r2 = random_double();
+    let (r1, r2) = random_2d();
 
     let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
     let phi = 2.0 * PI * r1;
@@ -42,14 +159,13 @@ pub fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
     Vec3::new(x, y, z)
 }
 
+//直接由二维样本映射到球面，不用拒绝采样，每次固定占用两维
 pub fn random_unit_vector() -> Vec3 {
-    loop {
-        let p = random_range(-1.0, 1.0);
-        let len_sq = p.length_squared();
-        if len_sq > 1e-160 && len_sq <= 1.0 {
-            return unit_vector(&p);
-        }
-    }
+    let (r1, r2) = random_2d();
+    let z = 1.0 - 2.0 * r1;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * r2;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
 pub fn random_on_hemisphere(normal: Vec3) -> Vec3 {
@@ -61,22 +177,23 @@ pub fn random_on_hemisphere(normal: Vec3) -> Vec3 {
     }
 }
 
+//Shirley-Chiu 同心映射，保持二维样本的分层
 pub fn random_in_unit_disk() -> Vec3 {
-    loop {
-        let p = Vec3::new(
-            random_double_range(-1.0, 1.0),
-            random_double_range(-1.0, 1.0),
-            0.0,
-        );
-        if p.length_squared() < 1.0 {
-            return p;
-        }
+    let (r1, r2) = random_2d();
+    let (a, b) = (2.0 * r1 - 1.0, 2.0 * r2 - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0);
     }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
 }
 
 pub fn random_cosine_direction() -> Vec3 {
-    let r1 = random_double();
-    let r2 = random_double();
+    let (r1, r2) = random_2d();
 
     let phi = 2.0 * PI * r1;
     let x = phi.cos() * r2.sqrt();
@@ -1,17 +1,177 @@
 use crate::camera::Camera;
+use crate::checkpoint::{AdaptiveSettings, Checkpoint, CheckpointError, RenderSettings};
+use crate::environment::Environment;
 use crate::hit_checker::{HitRecord, Hittable, HittableList, degrees_to_radians};
 use crate::interval::Interval;
+use crate::light::{Light, LightSample, LightSampler, LightSamplerKind, make_light_sampler};
 use crate::material::ScatterRecord;
-use crate::pdf::{HittablePdf, Pdf};
-use crate::random::random_double;
+use crate::pdf::MisHeuristic;
+use crate::random::{self, with_sample_stream};
 use crate::ray::Ray;
-use crate::sketchpad::Sketchpad;
+use crate::sampler::{Sampler, SamplerKind, make_sampler};
+use crate::sketchpad::{ImageFormat, SaveError, Sketchpad};
+use crate::tile::{TileOrder, make_tiles};
+use crate::tonemap::{ToneMapping, luminance};
 use crate::vec3::{Point3, Vec3, cross, unit_vector};
 use crate::vec3color::Color;
+use console::style;
 use indicatif::{ProgressBar, ProgressStyle};
-use rayon::prelude::*;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+//相机占用的维度：像素内位置 2 维、镜头 2 维、时间 1 维
+const CAMERA_DIMENSIONS: u32 = 5;
+//每次弹射预留的维度，用完后改用独立随机数
+const BOUNCE_DIMENSIONS: u32 = 16;
+
+pub struct ProgressiveOptions {
+    pub samples_per_pass: u32,              //0 表示每遍 sqrt_spp 个样本
+    pub preview_every: Option<u32>,         //每 N 遍写一次预览
+    pub preview_interval: Option<Duration>, //每隔一段时间写一次预览
+    pub preview_path: Option<PathBuf>,      //None 时直接覆盖 output
+}
+
+impl Default for ProgressiveOptions {
+    fn default() -> Self {
+        Self {
+            samples_per_pass: 0,
+            preview_every: None,
+            preview_interval: Some(Duration::from_secs(60)),
+            preview_path: None,
+        }
+    }
+}
+
+pub struct CheckpointOptions {
+    pub path: PathBuf,
+    pub interval: Duration, //两次写检查点之间的最短时间
+    pub resume: bool,       //从已有检查点继续渲染
+}
+
+//各类弹射的次数上限，总次数仍受 max_depth 限制
+pub struct PathOptions {
+    pub max_diffuse: u32,
+    pub max_specular: u32,
+    pub max_volume: u32,
+    pub roulette_depth: Option<u32>, //从第几次弹射起做俄罗斯轮盘赌，None 为不做
+}
+
+impl Default for PathOptions {
+    fn default() -> Self {
+        Self {
+            max_diffuse: u32::MAX,
+            max_specular: u32::MAX,
+            max_volume: u32::MAX,
+            roulette_depth: Some(3),
+        }
+    }
+}
+
+pub struct TileOptions {
+    pub size: u32,
+    pub order: TileOrder,
+}
+
+impl Default for TileOptions {
+    fn default() -> Self {
+        Self {
+            size: 32,
+            order: TileOrder::Scanline,
+        }
+    }
+}
+
+pub struct AdaptiveOptions {
+    pub min_spp: u32,
+    pub max_spp: Option<u32>, //None 时为 4 * spp
+    pub threshold: f64,       //亮度均值的相对标准误差低于该值即视为收敛
+    pub heatmap: Option<PathBuf>,
+}
+
+impl Default for AdaptiveOptions {
+    fn default() -> Self {
+        Self {
+            min_spp: 16,
+            max_spp: None,
+            threshold: 0.02,
+            heatmap: None,
+        }
+    }
+}
+
+pub struct RenderOptions {
+    pub output: PathBuf,
+    pub format: Option<ImageFormat>, //None 时按扩展名推断
+    pub tone_mapping: ToneMapping,   //只作用于 LDR 输出
+    pub progressive: Option<ProgressiveOptions>,
+    pub checkpoint: Option<CheckpointOptions>,
+    pub tiles: TileOptions,
+    pub adaptive: Option<AdaptiveOptions>,
+    pub sampler: SamplerKind,
+    pub mis: MisHeuristic,
+    pub paths: PathOptions,
+    pub light_sampler: LightSamplerKind,
+    pub scene_hash: u64, //记录在检查点中，恢复时用来确认是同一个场景
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            output: PathBuf::from("output/final_scene.png"),
+            format: None,
+            tone_mapping: ToneMapping::default(),
+            progressive: None,
+            checkpoint: None,
+            tiles: TileOptions::default(),
+            adaptive: None,
+            sampler: SamplerKind::Sobol,
+            mis: MisHeuristic::Power,
+            paths: PathOptions::default(),
+            light_sampler: LightSamplerKind::Bvh,
+            scene_hash: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RenderError {
+    Save(SaveError),
+    Checkpoint(CheckpointError),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Save(e) => write!(f, "{}", e),
+            RenderError::Checkpoint(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RenderError::Save(e) => Some(e),
+            RenderError::Checkpoint(e) => Some(e),
+        }
+    }
+}
+
+impl From<SaveError> for RenderError {
+    fn from(e: SaveError) -> Self {
+        RenderError::Save(e)
+    }
+}
+
+impl From<CheckpointError> for RenderError {
+    fn from(e: CheckpointError) -> Self {
+        RenderError::Checkpoint(e)
+    }
+}
 
 pub struct RayTracer {
     sketchpad: Sketchpad,
@@ -21,7 +181,8 @@ pub struct RayTracer {
     hittable_list: HittableList,
     samples_per_pixel: i32,
     max_depth: i32,
-    background: Color,
+    background: Arc<dyn Environment>,
+    options: RenderOptions,
 }
 
 impl RayTracer {
@@ -32,7 +193,7 @@ impl RayTracer {
         samples_per_pixel: i32,
         max_depth: i32,
         (defocus_angle, focus_dist): (f64, f64),
-        background: Color,
+        background: Arc<dyn Environment>,
     ) -> Self {
         let height = (width as f64 / aspect_ratio) as u32;
         let height = if height < 1 { 1 } else { height };
@@ -58,16 +219,12 @@ impl RayTracer {
         let defocus_disk_u = u * defocus_radius;
         let defocus_disk_v = v * defocus_radius;
 
-        let sqrt_spp = (samples_per_pixel as f64).sqrt() as u32;
-        let recip_sqrt_spp = 1.0 / sqrt_spp as f64;
-
         let camera = Camera::new(
             center,
             pixel_delta_u,
             pixel_delta_v,
             pixel00_loc,
             (defocus_angle, defocus_disk_u, defocus_disk_v),
-            recip_sqrt_spp,
         );
         let sketchpad = Sketchpad::new(width, aspect_ratio);
 
@@ -80,108 +237,566 @@ impl RayTracer {
             samples_per_pixel,
             max_depth,
             background,
+            options: RenderOptions::default(),
         }
     }
 
-    pub fn ray_color(&self, ray: &Ray, depth: i32, lights: Arc<HittableList>) -> Color {
-        if depth <= 0 {
-            return Color::new(0.0, 0.0, 0.0);
-        }
-        let mut rec = HitRecord::default();
-        //没击中物体返回背景色，击中不散射返回发光颜色
-        if self
-            .hittable_list
-            .hit(ray, Interval::new(0.001, f64::INFINITY), &mut rec)
-        {
+    pub fn set_options(&mut self, options: RenderOptions) {
+        self.options = options;
+    }
+
+    //迭代地追踪一条路径，在每个漫反射顶点同时做光源采样和材质采样，用 MIS 组合
+    pub fn ray_color(&self, mut ray: Ray, lights: &dyn LightSampler) -> Color {
+        let paths = &self.options.paths;
+        let mis = self.options.mis;
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        //上一个顶点按材质采样到这里时，击中的发光（含背景）应乘的 MIS 权重
+        let mut emission_weight = 1.0;
+        let (mut diffuse, mut specular, mut volume) = (0, 0, 0);
+
+        for bounce in 0..self.max_depth.max(0) as u32 {
+            //每次弹射从固定的维度开始取样
+            random::start_dimensions(
+                CAMERA_DIMENSIONS + bounce * BOUNCE_DIMENSIONS,
+                BOUNCE_DIMENSIONS,
+            );
+            let mut rec = HitRecord::default();
+            //没击中物体加上背景色，击中不散射只加发光颜色
+            if !self
+                .hittable_list
+                .hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec)
+            {
+                color += throughput * self.background.radiance(*ray.direction()) * emission_weight;
+                break;
+            }
             let mut s_rec = ScatterRecord::default();
-            let color_from_emission = rec.mat.emitted(ray, &rec, rec.u, rec.v, &rec.pos);
+            color +=
+                throughput * rec.mat.emitted(&ray, &rec, rec.u, rec.v, &rec.pos) * emission_weight;
             let mat = rec.mat.clone();
-            if !mat.scatter(ray, &mut rec, &mut s_rec) {
-                return color_from_emission;
+            if !mat.scatter(&ray, &mut rec, &mut s_rec) {
+                break;
             }
 
+            //镜面反射和折射无法显式连接光源，直接沿唯一方向追踪
             if s_rec.skip_pdf {
-                return s_rec.attenuation
-                    * self.ray_color(&s_rec.skip_pdf_ray, depth - 1, lights.clone());
-            }
+                specular += 1;
+                if specular > paths.max_specular {
+                    break;
+                }
+                throughput = throughput * s_rec.attenuation;
+                ray = s_rec.skip_pdf_ray;
+                emission_weight = 1.0;
+            } else {
+                let (count, limit) = if s_rec.volume {
+                    (&mut volume, paths.max_volume)
+                } else {
+                    (&mut diffuse, paths.max_diffuse)
+                };
+                *count += 1;
+                if *count > limit {
+                    break;
+                }
 
-            let light_ptr = Arc::new(HittablePdf::new(lights.clone(), rec.pos));
+                //下一次弹射已经到达深度上限时，材质采样击中的发光不会被计入，这里也不能计入
+                let sample_lights = !lights.is_empty() && bounce + 1 < self.max_depth as u32;
 
-            let generate = if random_double() < 0.5 {
-                light_ptr.generate()
-            } else {
-                s_rec.pdf_ptr.generate()
-            };
+                //显式连接光源：向光源采样一个方向，取该方向上第一个交点的发光
+                if sample_lights {
+                    match lights.sample(rec.pos) {
+                        LightSample::Direction(direction) => {
+                            let shadow_ray = Ray::new_with_time(rec.pos, direction, ray.time());
+                            let light_pdf = lights.pdf_value(rec.pos, direction);
+                            let f = mat.eval(&ray, &rec, &s_rec, &shadow_ray);
+                            if light_pdf > 0.0 && !is_black(&f) {
+                                let weight = mis.weight(light_pdf, s_rec.pdf_ptr.value(direction));
+                                color += throughput
+                                    * f
+                                    * self.emission_along(&shadow_ray)
+                                    * (weight / light_pdf);
+                            }
+                        }
+                        //点光源等只有这一种采样方式，不需要 MIS
+                        LightSample::Irradiance {
+                            direction,
+                            distance,
+                            irradiance,
+                        } => {
+                            let shadow_ray = Ray::new_with_time(rec.pos, direction, ray.time());
+                            let f = mat.eval(&ray, &rec, &s_rec, &shadow_ray);
+                            if !is_black(&f) && !self.occluded(&shadow_ray, distance) {
+                                color += throughput * f * irradiance;
+                            }
+                        }
+                    }
+                }
 
-            let scattered = Ray::new_with_time(rec.pos, generate, ray.time());
+                //按材质采样继续追踪，击中的发光在下一个顶点按 MIS 权重计入
+                let scattered = Ray::new_with_time(rec.pos, s_rec.pdf_ptr.generate(), ray.time());
+                let direction = *scattered.direction();
+                let pdf_value = s_rec.pdf_ptr.value(direction);
+                let f = mat.eval(&ray, &rec, &s_rec, &scattered);
+                if pdf_value <= 0.0 || is_black(&f) {
+                    break;
+                }
+                emission_weight = if sample_lights {
+                    mis.weight(pdf_value, lights.pdf_value(rec.pos, direction))
+                } else {
+                    1.0
+                };
+                throughput = throughput * f / pdf_value;
+                ray = scattered;
+            }
 
-            let direction = *scattered.direction();
-            let pdf_value = 0.5 * light_ptr.value(direction) + 0.5 * s_rec.pdf_ptr.value(direction);
+            //俄罗斯轮盘赌：贡献越小的路径越容易被终止，存活的路径按概率放大，保持无偏
+            if let Some(start) = paths.roulette_depth {
+                if bounce + 1 >= start {
+                    let survive = throughput
+                        .x()
+                        .max(throughput.y())
+                        .max(throughput.z())
+                        .min(0.95);
+                    if random::random_double() >= survive {
+                        break;
+                    }
+                    throughput /= survive;
+                }
+            }
+        }
+        color
+    }
 
-            let scattering_pdf = rec.mat.scattering_pdf(ray, &rec, &scattered);
+    //单位方向的光线在到达 distance 之前是否被挡住
+    fn occluded(&self, ray: &Ray, distance: f64) -> bool {
+        let mut rec = HitRecord::default();
+        self.hittable_list.hit(
+            ray,
+            Interval::new(0.001, distance * (1.0 - 1e-6) - 0.001),
+            &mut rec,
+        )
+    }
 
-            let color_from_scatter = (s_rec.attenuation
-                * scattering_pdf
-                * self.ray_color(&scattered, depth - 1, lights.clone()))
-                / pdf_value;
-            return color_from_emission + color_from_scatter;
+    //光线方向上第一个交点的发光，没有交点时为背景
+    fn emission_along(&self, ray: &Ray) -> Color {
+        let mut rec = HitRecord::default();
+        if self
+            .hittable_list
+            .hit(ray, Interval::new(0.001, f64::INFINITY), &mut rec)
+        {
+            rec.mat.emitted(ray, &rec, rec.u, rec.v, &rec.pos)
+        } else {
+            self.background.radiance(*ray.direction())
         }
-        self.background
     }
 
-    pub fn render(&mut self, lights: Arc<HittableList>) {
+    //自适应采样的 (最少, 最多采样数, 收敛阈值)
+    fn adaptive_schedule(&self) -> Option<(u32, u32, f64)> {
+        let spp = self.samples_per_pixel.max(1) as u32;
+        self.options.adaptive.as_ref().map(|a| {
+            let max_spp = a.max_spp.unwrap_or(spp.saturating_mul(4));
+            (a.min_spp.min(max_spp), max_spp, a.threshold)
+        })
+    }
+
+    pub fn settings(&self) -> RenderSettings {
+        RenderSettings {
+            width: self.width,
+            height: self.height,
+            samples_per_pixel: self.samples_per_pixel,
+            max_depth: self.max_depth,
+            seed: random::seed(),
+            sampler: self.options.sampler.name().to_string(),
+            max_diffuse: self.options.paths.max_diffuse,
+            max_specular: self.options.paths.max_specular,
+            max_volume: self.options.paths.max_volume,
+            roulette_depth: self.options.paths.roulette_depth,
+            scene_hash: self.options.scene_hash,
+            adaptive: self
+                .adaptive_schedule()
+                .map(|(min_spp, max_spp, threshold)| AdaptiveSettings {
+                    min_spp,
+                    max_spp,
+                    threshold,
+                }),
+        }
+    }
+
+    pub fn render(&mut self, lights: Vec<Light>) -> Result<(), RenderError> {
+        let lights = make_light_sampler(self.options.light_sampler, lights);
         let width = self.width;
         let height = self.height;
-        let samples_per_pixel = self.samples_per_pixel;
-        let max_depth = self.max_depth;
 
-        let sqrt_spp = (samples_per_pixel as f64).sqrt() as u32;
-        let pixel_samples_scale = 1.0 / (sqrt_spp * sqrt_spp) as f64;
+        let spp = self.samples_per_pixel.max(1) as u32;
+        let adaptive = self.adaptive_schedule();
+        //自适应采样时 sampler 按最大采样数生成样本，任意前缀都能较均匀地铺满像素
+        let total_samples = adaptive.map_or(spp, |(_, max_spp, _)| max_spp);
+        let sqrt_spp = ((total_samples as f64).sqrt() as u32).max(1);
+        let sampler = make_sampler(
+            self.options.sampler,
+            total_samples,
+            (width, height),
+            random::seed(),
+        );
+        //渐进式渲染、写检查点或自适应采样时才分遍
+        let samples_per_pass = match (&self.options.progressive, &self.options.checkpoint) {
+            (Some(p), _) if p.samples_per_pass > 0 => p.samples_per_pass.min(total_samples),
+            (None, None) if adaptive.is_none() => total_samples,
+            _ => sqrt_spp,
+        };
+        let mut schedule = SampleSchedule {
+            total: total_samples,
+            step: samples_per_pass,
+            adaptive: adaptive
+                .map(|(min_spp, _, threshold)| (min_spp.min(total_samples), threshold)),
+            cutoff: 0.0,
+        };
+        let tiles = make_tiles(
+            width,
+            height,
+            self.options.tiles.size,
+            self.options.tiles.order,
+        );
+
+        let total_pixels = (width * height) as usize;
+        let (mut accum, mut accum_sq, mut counts) = match &self.options.checkpoint {
+            Some(checkpoint) if checkpoint.resume => {
+                let saved = Checkpoint::load(&checkpoint.path)?;
+                saved.check_compatible(&self.settings())?;
+                println!(
+                    "Resume from checkpoint \"{}\"",
+                    style(checkpoint.path.display()).yellow()
+                );
+                (saved.accum, saved.accum_sq, saved.counts)
+            }
+            _ => (
+                vec![Color::new(0.0, 0.0, 0.0); total_pixels],
+                vec![0.0; total_pixels],
+                vec![0u32; total_pixels],
+            ),
+        };
 
-        let total_pixels = width * height;
-        let progress = Arc::new(AtomicUsize::new(0));
-        let mut pixels = vec![Color::new(0.0, 0.0, 0.0); total_pixels as usize];
+        //自适应采样时 spp 是平均预算，收敛像素省下的样本留给噪点多的区域
+        let mut spent: u64 = counts.iter().map(|&c| c as u64).sum();
+        let budget = if adaptive.is_some() {
+            spp as u64 * total_pixels as u64
+        } else {
+            u64::MAX
+        };
+        let (mut active, remaining) = schedule.pending(&accum, &accum_sq, &counts);
+        let passes = if adaptive.is_some() {
+            None
+        } else {
+            let done = counts.iter().copied().min().unwrap_or(0).min(total_samples);
+            Some((total_samples - done).div_ceil(samples_per_pass))
+        };
 
         // 初始化进度条
-        let pb = ProgressBar::new(total_pixels as u64);
+        let pb = ProgressBar::new(remaining.min(budget.saturating_sub(spent)));
         pb.set_style(
             ProgressStyle::default_bar()
-                .template("渲染中: [{bar:40.cyan/blue}] {percent}% | {pos}/{len} 像素")
+                .template("渲染中: [{bar:40.cyan/blue}] {percent}% | {pos}/{len} 样本 {msg}")
                 .unwrap()
                 .progress_chars("##-"),
         );
 
-        pixels
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(index, pixel)| {
-                let x = index as u32 % width;
-                let y = index as u32 / width;
+        let mut last_preview = Instant::now();
+        let mut last_checkpoint = Instant::now();
+        let mut pass = 0;
+        while active > 0 && spent < budget {
+            match passes {
+                Some(passes) if passes > 1 => {
+                    pb.set_message(format!("第 {}/{} 遍", pass + 1, passes))
+                }
+                None => pb.set_message(format!("第 {} 遍，{} 像素未收敛", pass + 1, active)),
+                _ => {}
+            }
+            if adaptive.is_some() {
+                schedule.limit_to_budget(&accum, &accum_sq, &counts, budget - spent);
+            }
+
+            //工作线程按顺序领取 tile，保证 tile_order 决定的渲染顺序
+            let next_tile = AtomicUsize::new(0);
+            let taken = AtomicU64::new(0);
+            let buffers = Mutex::new((&mut accum, &mut accum_sq, &mut counts));
+            rayon::scope(|scope| {
+                for _ in 0..rayon::current_num_threads() {
+                    scope.spawn(|_| {
+                        while let Some(tile) = tiles.get(next_tile.fetch_add(1, Ordering::Relaxed))
+                        {
+                            let index = |(x, y): (u32, u32)| (y * width + x) as usize;
+                            let plans: Vec<Range<u32>> = {
+                                let buffers = buffers.lock().unwrap();
+                                tile.pixels()
+                                    .map(|p| {
+                                        let i = index(p);
+                                        schedule.next(&buffers.0[i], buffers.1[i], buffers.2[i])
+                                    })
+                                    .collect()
+                            };
 
-                let mut color = Color::new(0.0, 0.0, 0.0);
+                            let results: Vec<(Color, f64, u32)> = tile
+                                .pixels()
+                                .zip(&plans)
+                                .map(|((x, y), samples)| {
+                                    let (color, lum_sq) =
+                                        self.sample_pixel(x, y, samples.clone(), &sampler, &lights);
+                                    (color, lum_sq, samples.end)
+                                })
+                                .collect();
 
-                for s_j in 0..sqrt_spp {
-                    for s_i in 0..sqrt_spp {
-                        let r = self.camera.get_ray(x, y, s_i, s_j);
-                        color += self.ray_color(&r, max_depth, lights.clone());
+                            let mut buffers = buffers.lock().unwrap();
+                            for (p, (color, lum_sq, last)) in tile.pixels().zip(results) {
+                                buffers.0[index(p)] += color;
+                                buffers.1[index(p)] += lum_sq;
+                                buffers.2[index(p)] = last;
+                            }
+                            drop(buffers);
+                            let n: u64 = plans.iter().map(|r| r.len() as u64).sum();
+                            taken.fetch_add(n, Ordering::Relaxed);
+                            pb.inc(n);
+                        }
+                    });
+                }
+            });
+            spent += taken.into_inner();
+            pass += 1;
+
+            active = schedule.pending(&accum, &accum_sq, &counts).0;
+            if active == 0 || spent >= budget {
+                break;
+            }
+            if let Some(checkpoint) = &self.options.checkpoint {
+                if last_checkpoint.elapsed() >= checkpoint.interval {
+                    self.write_checkpoint(&checkpoint.path, &accum, &accum_sq, &counts, &pb);
+                    last_checkpoint = Instant::now();
+                }
+            }
+            if let Some(progressive) = &self.options.progressive {
+                let every_due = progressive
+                    .preview_every
+                    .is_some_and(|n| n > 0 && pass % n == 0);
+                let interval_due = progressive
+                    .preview_interval
+                    .is_some_and(|t| last_preview.elapsed() >= t);
+                if every_due || interval_due {
+                    let (path, format) = match &progressive.preview_path {
+                        Some(path) => (path.clone(), self.preview_format(path)),
+                        None => (self.options.output.clone(), self.options.format),
+                    };
+                    self.develop(&accum, &counts);
+                    if let Err(e) = pb.suspend(|| self.sketchpad.save(&path, format)) {
+                        pb.suspend(|| eprintln!("预览图保存失败: {}", e));
                     }
+                    last_preview = Instant::now();
                 }
-                *pixel = color * pixel_samples_scale;
+            }
+        }
+
+        pb.finish_with_message("渲染完成！");
+        if adaptive.is_some() {
+            println!(
+                "平均每像素 {:.1} 个样本",
+                spent as f64 / total_pixels as f64
+            );
+        }
+
+        //渲染完成后也保留检查点，之后可以调大 spp 继续追加样本
+        if let Some(checkpoint) = &self.options.checkpoint {
+            self.write_checkpoint(&checkpoint.path, &accum, &accum_sq, &counts, &pb);
+        }
+
+        self.develop(&accum, &counts);
+        self.sketchpad
+            .save(&self.options.output, self.options.format)?;
 
-                progress.fetch_add(1, Ordering::Relaxed);
-                pb.set_position(progress.load(Ordering::Relaxed) as u64);
+        if let Some(path) = self
+            .options
+            .adaptive
+            .as_ref()
+            .and_then(|a| a.heatmap.as_ref())
+        {
+            self.heatmap(&counts, total_samples).save(path, None)?;
+        }
+        Ok(())
+    }
+
+    //对像素 (x, y) 追加 samples 范围内的样本，返回辐射度之和与亮度平方和
+    fn sample_pixel(
+        &self,
+        x: u32,
+        y: u32,
+        samples: Range<u32>,
+        sampler: &Arc<dyn Sampler>,
+        lights: &Arc<dyn LightSampler>,
+    ) -> (Color, f64) {
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        let mut lum_sq = 0.0;
+        for s in samples {
+            let sample = with_sample_stream(sampler, (x, y), s, || {
+                random::start_dimensions(0, CAMERA_DIMENSIONS);
+                let r = self.camera.get_ray(x, y);
+                self.ray_color(r, lights.as_ref())
             });
+            lum_sq += luminance(&sample).powi(2);
+            color += sample;
+        }
+        (color, lum_sq)
+    }
 
-        pb.finish_with_message("渲染完成！");
+    fn write_checkpoint(
+        &self,
+        path: &Path,
+        accum: &[Color],
+        accum_sq: &[f64],
+        counts: &[u32],
+        pb: &ProgressBar,
+    ) {
+        let checkpoint = Checkpoint {
+            settings: self.settings(),
+            accum: accum.to_vec(),
+            accum_sq: accum_sq.to_vec(),
+            counts: counts.to_vec(),
+        };
+        if let Err(e) = checkpoint.save(path) {
+            pb.suspend(|| eprintln!("检查点保存失败: {}", e));
+        }
+    }
+
+    //预览图按自己的扩展名选择格式，与输出同类时沿用输出的参数（如 JPEG 质量），没有可识别的扩展名时和输出相同
+    fn preview_format(&self, path: &Path) -> Option<ImageFormat> {
+        match ImageFormat::from_path(path) {
+            Some(format)
+                if self.options.format.as_ref().is_some_and(|output| {
+                    std::mem::discriminant(output) == std::mem::discriminant(&format)
+                }) =>
+            {
+                self.options.format
+            }
+            Some(format) => Some(format),
+            None => self.options.format,
+        }
+    }
+
+    //将累积的辐射度按各像素的样本数平均后写入 sketchpad
+    fn develop(&mut self, accum: &[Color], counts: &[u32]) {
+        self.sketchpad.set_tone_mapping(self.options.tone_mapping);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let idx = (y * self.width + x) as usize;
+                let color = if counts[idx] == 0 {
+                    Color::new(0.0, 0.0, 0.0)
+                } else {
+                    accum[idx] / counts[idx] as f64
+                };
+                self.sketchpad.draw(x, y, color);
+            }
+        }
+    }
+
+    //各像素采样数的热度图，从深蓝（少）到红（多）
+    fn heatmap(&self, counts: &[u32], max_samples: u32) -> Sketchpad {
+        let mut heatmap = Sketchpad::with_size(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let t = counts[(y * self.width + x) as usize] as f64 / max_samples as f64;
+                heatmap.draw(x, y, heat_color(t));
+            }
+        }
+        heatmap
+    }
+}
+
+//本遍为每个像素安排的样本范围
+struct SampleSchedule {
+    total: u32,
+    step: u32,
+    adaptive: Option<(u32, f64)>, //(min_spp, threshold)
+    cutoff: f64,                  //预算不足时，本遍只补误差不低于 cutoff 的像素
+}
+
+impl SampleSchedule {
+    fn next(&self, sum: &Color, lum_sq: f64, count: u32) -> Range<u32> {
+        let Some((min_spp, threshold)) = self.adaptive else {
+            return count..(count + self.step).min(self.total).max(count);
+        };
+        if count < min_spp {
+            return count..min_spp;
+        }
+        if count >= self.total
+            || relative_error(luminance(sum), lum_sq, count) < threshold.max(self.cutoff)
+        {
+            return count..count;
+        }
+        count..(count + self.step).min(self.total)
+    }
 
-        // 将结果写入 sketchpad
-        for y in 0..height {
-            for x in 0..width {
-                let idx = y * width + x;
-                self.sketchpad.draw(x, y, pixels[idx as usize]);
+    //(仍需采样的像素数, 这些像素到达上限前最多还需要的样本数)
+    fn pending(&self, accum: &[Color], accum_sq: &[f64], counts: &[u32]) -> (usize, u64) {
+        (0..counts.len())
+            .filter(|&i| !self.next(&accum[i], accum_sq[i], counts[i]).is_empty())
+            .fold((0, 0), |(pixels, samples), i| {
+                (
+                    pixels + 1,
+                    samples + self.total.saturating_sub(counts[i]) as u64,
+                )
+            })
+    }
+
+    //剩余预算不够所有未收敛像素再走一遍时，优先把样本留给误差最大的像素
+    fn limit_to_budget(&mut self, accum: &[Color], accum_sq: &[f64], counts: &[u32], left: u64) {
+        self.cutoff = 0.0;
+        let mut planned: Vec<(f64, u64)> = (0..counts.len())
+            .filter_map(|i| {
+                let samples = self.next(&accum[i], accum_sq[i], counts[i]);
+                let error = relative_error(luminance(&accum[i]), accum_sq[i], counts[i]);
+                (!samples.is_empty()).then_some((error, samples.len() as u64))
+            })
+            .collect();
+        if planned.iter().map(|&(_, n)| n).sum::<u64>() <= left {
+            return;
+        }
+        planned.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let mut used = 0;
+        for (error, n) in planned {
+            used += n;
+            if used >= left {
+                self.cutoff = error;
+                break;
             }
         }
+    }
+}
 
-        self.sketchpad.save();
+//BSDF 在该方向上没有贡献
+fn is_black(color: &Color) -> bool {
+    color.x() <= 0.0 && color.y() <= 0.0 && color.z() <= 0.0
+}
+
+//像素亮度均值的相对标准误差，暗像素按 0.01 的亮度计算，避免永远无法收敛
+fn relative_error(sum: f64, sum_sq: f64, count: u32) -> f64 {
+    if count < 2 {
+        return f64::INFINITY;
     }
+    let n = count as f64;
+    let mean = sum / n;
+    let variance = ((sum_sq - sum * mean) / (n - 1.0)).max(0.0);
+    (variance / n).sqrt() / mean.max(0.01)
+}
+
+fn heat_color(t: f64) -> Color {
+    const STOPS: [(f64, f64, f64); 5] = [
+        (0.0, 0.0, 0.3),
+        (0.0, 0.3, 1.0),
+        (0.0, 0.9, 0.6),
+        (1.0, 0.9, 0.0),
+        (1.0, 0.0, 0.0),
+    ];
+    let t = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f64;
+    let i = (t as usize).min(STOPS.len() - 2);
+    let f = t - i as f64;
+    let (a, b) = (STOPS[i], STOPS[i + 1]);
+    let lerp = |a: f64, b: f64| a + (b - a) * f;
+    //热度图只保存为 LDR，sketchpad 输出时会做 gamma 2，这里先平方抵消
+    let c = Color::new(lerp(a.0, b.0), lerp(a.1, b.1), lerp(a.2, b.2));
+    c * c
 }
@@ -0,0 +1,448 @@
+use std::sync::Arc;
+
+//按 (像素, 样本序号, 维度) 给出 [0, 1) 内的样本值，不保存状态，可以被多个线程共享
+pub trait Sampler: Send + Sync {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64;
+    //占用 dimension 和 dimension + 1 两维，两维联合分层
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> (f64, f64);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,  //Owen 扰乱的 Sobol 序列
+    ZSobol, //按像素 Morton 序排列的 Sobol 序列，误差呈蓝噪声分布
+}
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "independent" | "random" => Some(SamplerKind::Independent),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            "zsobol" | "z-order" | "blue-noise" => Some(SamplerKind::ZSobol),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+            SamplerKind::ZSobol => "zsobol",
+        }
+    }
+}
+
+pub fn make_sampler(
+    kind: SamplerKind,
+    samples_per_pixel: u32,
+    (width, height): (u32, u32),
+    seed: u64,
+) -> Arc<dyn Sampler> {
+    match kind {
+        SamplerKind::Independent => Arc::new(IndependentSampler::new(seed)),
+        SamplerKind::Stratified => Arc::new(StratifiedSampler::new(samples_per_pixel, seed)),
+        SamplerKind::Halton => Arc::new(HaltonSampler::new(seed)),
+        SamplerKind::Sobol => Arc::new(SobolSampler::new(seed)),
+        SamplerKind::ZSobol => {
+            Arc::new(ZSobolSampler::new(samples_per_pixel, (width, height), seed))
+        }
+    }
+}
+
+pub struct IndependentSampler {
+    seed: u64,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        hashed_sample(self.seed, pixel, index, dimension)
+    }
+
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> (f64, f64) {
+        (
+            self.get_1d(pixel, index, dimension),
+            self.get_1d(pixel, index, dimension + 1),
+        )
+    }
+}
+
+//每一维把像素的 samples 个样本分到等宽的层里，各维使用不同的随机排列
+pub struct StratifiedSampler {
+    samples: u32,
+    sqrt_samples: u32,
+    seed: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        let samples = samples_per_pixel.max(1);
+        Self {
+            samples,
+            sqrt_samples: ((samples as f64).sqrt() as u32).max(1),
+            seed,
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        let n = self.samples;
+        //超出 samples 的样本按轮重新分层
+        let h = hash(&[
+            self.seed,
+            pixel_id(pixel),
+            dimension as u64,
+            (index / n) as u64,
+        ]);
+        let stratum = permute(index % n, n, h as u32);
+        (stratum as f64 + to_unit(hash(&[h, index as u64]))) / n as f64
+    }
+
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> (f64, f64) {
+        let sqrt_n = self.sqrt_samples;
+        let n = sqrt_n * sqrt_n;
+        let h = hash(&[
+            self.seed,
+            pixel_id(pixel),
+            dimension as u64,
+            (index / n) as u64,
+        ]);
+        let stratum = permute(index % n, n, h as u32);
+        let jitter = hash(&[h, index as u64]);
+        (
+            ((stratum % sqrt_n) as f64 + to_unit(jitter)) / sqrt_n as f64,
+            ((stratum / sqrt_n) as f64 + to_unit(mix64(jitter))) / sqrt_n as f64,
+        )
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+//第 d 维使用第 d 个素数为底的根式反演，每个像素独立地对各位数字做随机平移
+pub struct HaltonSampler {
+    seed: u64,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        //维度太高时 Halton 序列各维之间相关性很强，改用独立随机数
+        let Some(&base) = PRIMES.get(dimension as usize) else {
+            return hashed_sample(self.seed, pixel, index, dimension);
+        };
+        let scramble = hash(&[self.seed, pixel_id(pixel), dimension as u64]);
+        scrambled_radical_inverse(base, index, scramble)
+    }
+
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> (f64, f64) {
+        (
+            self.get_1d(pixel, index, dimension),
+            self.get_1d(pixel, index, dimension + 1),
+        )
+    }
+}
+
+fn scrambled_radical_inverse(base: u32, mut index: u32, scramble: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut factor = inv_base;
+    let mut value = 0.0;
+    let mut digit_index = 0;
+    //index 用完之后的 0 位经过平移也会变成非零数字，一直算到超出 f64 精度为止
+    while factor > 1e-16 {
+        let shift = (hash(&[scramble, digit_index]) % base as u64) as u32;
+        let digit = (index % base + shift) % base;
+        value += digit as f64 * factor;
+        index /= base;
+        factor *= inv_base;
+        digit_index += 1;
+    }
+    value.min(ONE_MINUS_EPSILON)
+}
+
+//Burley 2020 的做法：每次取样都用各自的种子打乱样本序号，再对 Sobol 前两维做 Owen 扰乱
+pub struct SobolSampler {
+    seed: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        let h = hash(&[self.seed, pixel_id(pixel), dimension as u64]);
+        let index = owen_scramble(index, h as u32) as u64;
+        u32_to_unit(owen_scramble(sobol(index, 0), (h >> 32) as u32))
+    }
+
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> (f64, f64) {
+        let h = hash(&[self.seed, pixel_id(pixel), dimension as u64]);
+        let index = owen_scramble(index, h as u32) as u64;
+        let bits = mix64(h);
+        (
+            u32_to_unit(owen_scramble(sobol(index, 0), bits as u32)),
+            u32_to_unit(owen_scramble(sobol(index, 1), (bits >> 32) as u32)),
+        )
+    }
+}
+
+//Ahmed & Wonka 2020：相邻像素取同一条 Sobol 序列中相邻的一段，
+//并逐层随机置换 Morton 码的四进制数位，使像素间的误差呈蓝噪声分布
+pub struct ZSobolSampler {
+    seed: u64,
+    log2_spp: u32,
+    base4_digits: u32,
+}
+
+impl ZSobolSampler {
+    pub fn new(samples_per_pixel: u32, (width, height): (u32, u32), seed: u64) -> Self {
+        let log2_spp = samples_per_pixel
+            .max(1)
+            .next_power_of_two()
+            .trailing_zeros();
+        let log2_resolution = width
+            .max(height)
+            .max(1)
+            .next_power_of_two()
+            .trailing_zeros();
+        Self {
+            seed,
+            log2_spp,
+            base4_digits: log2_resolution + log2_spp.div_ceil(2),
+        }
+    }
+
+    fn sample_index(&self, (x, y): (u32, u32), index: u32, dimension: u32) -> u64 {
+        let spp_mask = (1u64 << self.log2_spp) - 1;
+        let morton = (encode_morton2(x, y) << self.log2_spp) | (index as u64 & spp_mask);
+        //超出每像素样本数的部分当作新的一轮，用不同的置换
+        let salt =
+            0x5555_5555u64.wrapping_mul(dimension as u64) ^ ((index as u64) >> self.log2_spp);
+
+        let odd_log2 = self.log2_spp & 1;
+        let mut sample_index = 0u64;
+        for i in (odd_log2..self.base4_digits).rev() {
+            let shift = 2 * i - odd_log2;
+            let digit = (morton >> shift) & 3;
+            let higher = morton >> (shift + 2);
+            let p = (mix64(higher ^ salt) >> 24) % 24;
+            sample_index |= (PERMUTATIONS_4[p as usize][digit as usize] as u64) << shift;
+        }
+        if odd_log2 == 1 {
+            sample_index |= (morton & 1) ^ (mix64((morton >> 1) ^ salt) & 1);
+        }
+        sample_index
+    }
+}
+
+impl Sampler for ZSobolSampler {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        let sample_index = self.sample_index(pixel, index, dimension);
+        let h = hash(&[self.seed, dimension as u64]);
+        u32_to_unit(owen_scramble(sobol(sample_index, 0), h as u32))
+    }
+
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> (f64, f64) {
+        let sample_index = self.sample_index(pixel, index, dimension);
+        let h = hash(&[self.seed, dimension as u64]);
+        (
+            u32_to_unit(owen_scramble(sobol(sample_index, 0), h as u32)),
+            u32_to_unit(owen_scramble(sobol(sample_index, 1), (h >> 32) as u32)),
+        )
+    }
+}
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+const PERMUTATIONS_4: [[u8; 4]; 24] = [
+    [0, 1, 2, 3],
+    [0, 1, 3, 2],
+    [0, 2, 1, 3],
+    [0, 2, 3, 1],
+    [0, 3, 2, 1],
+    [0, 3, 1, 2],
+    [1, 0, 2, 3],
+    [1, 0, 3, 2],
+    [1, 2, 0, 3],
+    [1, 2, 3, 0],
+    [1, 3, 2, 0],
+    [1, 3, 0, 2],
+    [2, 1, 0, 3],
+    [2, 1, 3, 0],
+    [2, 0, 1, 3],
+    [2, 0, 3, 1],
+    [2, 3, 0, 1],
+    [2, 3, 1, 0],
+    [3, 1, 2, 0],
+    [3, 1, 0, 2],
+    [3, 2, 1, 0],
+    [3, 2, 0, 1],
+    [3, 0, 2, 1],
+    [3, 0, 1, 2],
+];
+
+//Sobol 生成矩阵的列，第 0 维为 van der Corput 序列，第 1 维来自 Joe & Kuo 的方向数
+const SOBOL_MATRIX_SIZE: usize = 52;
+const SOBOL_MATRICES: [[u32; SOBOL_MATRIX_SIZE]; 2] =
+    [sobol_matrix(0, 0, [0; 3]), sobol_matrix(1, 0, [1, 0, 0])];
+
+//s 为本原多项式的次数，a 为其中间项系数，m 为初始方向数；s 为 0 时生成单位阵
+const fn sobol_matrix(s: usize, a: u64, m: [u64; 3]) -> [u32; SOBOL_MATRIX_SIZE] {
+    let mut v = [0u64; SOBOL_MATRIX_SIZE];
+    let mut i = 0;
+    while i < SOBOL_MATRIX_SIZE {
+        if s == 0 || i < s {
+            let m_i = if s == 0 { 1 } else { m[i] };
+            v[i] = m_i << (63 - i);
+        } else {
+            v[i] = v[i - s] ^ (v[i - s] >> s);
+            let mut k = 1;
+            while k < s {
+                if (a >> (s - 1 - k)) & 1 == 1 {
+                    v[i] ^= v[i - k];
+                }
+                k += 1;
+            }
+        }
+        i += 1;
+    }
+    let mut columns = [0u32; SOBOL_MATRIX_SIZE];
+    let mut i = 0;
+    while i < SOBOL_MATRIX_SIZE {
+        columns[i] = (v[i] >> 32) as u32;
+        i += 1;
+    }
+    columns
+}
+
+fn sobol(mut index: u64, dimension: usize) -> u32 {
+    let mut value = 0;
+    let mut column = 0;
+    while index != 0 && column < SOBOL_MATRIX_SIZE {
+        if index & 1 == 1 {
+            value ^= SOBOL_MATRICES[dimension][column];
+        }
+        index >>= 1;
+        column += 1;
+    }
+    value
+}
+
+//Burley 2020 基于哈希的 nested uniform scramble
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x ^= x.wrapping_mul(0x3d20_adea);
+    x = x.wrapping_add(seed);
+    x = x.wrapping_mul((seed >> 16) | 1);
+    x ^= x.wrapping_mul(0x0552_6c56);
+    x ^= x.wrapping_mul(0x53a2_2864);
+    x.reverse_bits()
+}
+
+//Kensler 2013：以 p 为种子生成 0..n 上的随机排列，返回 i 的像
+fn permute(mut i: u32, n: u32, p: u32) -> u32 {
+    if n <= 1 {
+        return 0;
+    }
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i + p) % n
+}
+
+fn encode_morton2(x: u32, y: u32) -> u64 {
+    fn spread(v: u32) -> u64 {
+        let mut v = v as u64;
+        v = (v | (v << 16)) & 0x0000_ffff_0000_ffff;
+        v = (v | (v << 8)) & 0x00ff_00ff_00ff_00ff;
+        v = (v | (v << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+        v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+        v = (v | (v << 1)) & 0x5555_5555_5555_5555;
+        v
+    }
+    spread(x) | (spread(y) << 1)
+}
+
+//与线程、调度顺序无关的独立随机数，只取决于 (种子, 像素, 样本, 维度)
+pub fn hashed_sample(seed: u64, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+    to_unit(hash(&[
+        seed,
+        pixel_id(pixel),
+        index as u64,
+        dimension as u64,
+    ]))
+}
+
+fn pixel_id((x, y): (u32, u32)) -> u64 {
+    ((x as u64) << 32) | y as u64
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x243F_6A88_85A3_08D3, |h, &v| {
+        mix64(h ^ v.wrapping_add(0x9E37_79B9_7F4A_7C15))
+    })
+}
+
+//SplitMix64 的终结函数，输入相差一位时输出的各位也几乎独立
+fn mix64(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+fn to_unit(bits: u64) -> f64 {
+    (bits >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
+fn u32_to_unit(bits: u32) -> f64 {
+    bits as f64 * (1.0 / (1u64 << 32) as f64)
+}
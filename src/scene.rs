@@ -0,0 +1,1111 @@
+use crate::bvh::BvhNode;
+use crate::checkpoint::scene_hash;
+use crate::disney::{Disney, DisneyParams};
+use crate::environment::{ConstantBackground, Environment, EnvironmentMap};
+use crate::hit_checker::{Hittable, HittableList};
+use crate::light::Light;
+use crate::material::{
+    AlphaMask, AreaSpotLight, Conductor, Dielectric, DiffuseLight, DummyMaterial, Isotropic,
+    Lambertian, Material, Metal, RoughDielectric, conductor_preset,
+};
+use crate::modeling::{ConstantMedium, ExcludeLight, Quad, RotateY, Sphere, Translate, make_box};
+use crate::obj::{DEFAULT_CREASE_ANGLE, load_model};
+use crate::punctual::{DirectionalLight, PointLight, PunctualLight, SpotLight};
+use crate::raytracer::{RayTracer, RenderError, RenderOptions};
+use crate::sky::{PreethamSky, SkyOptions};
+use crate::texture::{
+    CheckerTexture, ImageTexture, MappedTexture, NoiseTexture, SolidColor, Texture,
+};
+use crate::vec3::{Vec3, cross};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    UnsupportedFormat(String),
+    Parse {
+        path: String,
+        field: String,
+        message: String,
+    },
+    Invalid {
+        field: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => {
+                write!(f, "cannot read scene file '{}': {}", path, source)
+            }
+            SceneError::UnsupportedFormat(path) => write!(
+                f,
+                "unsupported scene format '{}', expected a .json or .toml file",
+                path
+            ),
+            SceneError::Parse {
+                path,
+                field,
+                message,
+            } => write!(f, "{}: field '{}': {}", path, field, message),
+            SceneError::Invalid { field, message } => write!(f, "field '{}': {}", field, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+fn invalid(field: impl Into<String>, message: impl Into<String>) -> SceneError {
+    SceneError::Invalid {
+        field: field.into(),
+        message: message.into(),
+    }
+}
+
+fn default_aspect_ratio() -> f64 {
+    16.0 / 9.0
+}
+
+fn default_image_width() -> u32 {
+    400
+}
+
+fn default_samples_per_pixel() -> i32 {
+    100
+}
+
+fn default_max_depth() -> i32 {
+    50
+}
+
+fn default_v_fov() -> f64 {
+    90.0
+}
+
+fn default_vup() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_focus_dist() -> f64 {
+    10.0
+}
+
+fn default_one() -> f64 {
+    1.0
+}
+
+fn default_crease_angle() -> f64 {
+    DEFAULT_CREASE_ANGLE
+}
+
+fn default_background() -> [f64; 3] {
+    [0.0, 0.0, 0.0]
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+fn default_ground_albedo() -> [f64; 3] {
+    [0.3, 0.3, 0.3]
+}
+
+fn default_sun_size() -> f64 {
+    0.53
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraConfig {
+    #[serde(default = "default_aspect_ratio")]
+    pub aspect_ratio: f64,
+    #[serde(default = "default_image_width")]
+    pub image_width: u32,
+    #[serde(default = "default_samples_per_pixel")]
+    pub samples_per_pixel: i32,
+    #[serde(default = "default_max_depth")]
+    pub max_depth: i32,
+    #[serde(default = "default_v_fov")]
+    pub v_fov: f64,
+    pub look_from: [f64; 3],
+    pub look_at: [f64; 3],
+    #[serde(default = "default_vup")]
+    pub vup: [f64; 3],
+    #[serde(default)]
+    pub defocus_angle: f64,
+    #[serde(default = "default_focus_dist")]
+    pub focus_dist: f64,
+}
+
+impl CameraConfig {
+    pub fn validate(&self) -> Result<(), SceneError> {
+        if !self.aspect_ratio.is_finite() || self.aspect_ratio <= 0.0 {
+            return Err(invalid(
+                "camera.aspect_ratio",
+                "must be a finite positive number",
+            ));
+        }
+        if self.image_width == 0 {
+            return Err(invalid("camera.image_width", "must be at least 1"));
+        }
+        if self.samples_per_pixel < 1 {
+            return Err(invalid("camera.samples_per_pixel", "must be at least 1"));
+        }
+        if self.max_depth < 1 {
+            return Err(invalid("camera.max_depth", "must be at least 1"));
+        }
+        if !(self.v_fov > 0.0 && self.v_fov < 180.0) {
+            return Err(invalid("camera.v_fov", "must be between 0 and 180 degrees"));
+        }
+        if !self.focus_dist.is_finite() || self.focus_dist <= 0.0 {
+            return Err(invalid(
+                "camera.focus_dist",
+                "must be a finite positive number",
+            ));
+        }
+        if (to_vec3(self.look_from) - to_vec3(self.look_at)).near_zero() {
+            return Err(invalid(
+                "camera.look_at",
+                "must differ from camera.look_from",
+            ));
+        }
+        Ok(())
+    }
+}
+
+//颜色既可以直接写成 [r, g, b]，也可以引用 textures 中的纹理名
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum ColorSource {
+    Color([f64; 3]),
+    Texture(String),
+}
+
+//标量参数可以直接写数值，也可以引用纹理（取 r 通道）
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum ScalarSource {
+    Value(f64),
+    Texture(String),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Solid {
+        color: [f64; 3],
+    },
+    Checker {
+        scale: f64,
+        even: ColorSource,
+        odd: ColorSource,
+    },
+    Image {
+        file: String,
+    },
+    Noise {
+        #[serde(default = "default_one")]
+        scale: f64,
+    },
+    Mapped {
+        color: String,
+        normal: Option<String>,
+        alpha: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: ColorSource,
+    },
+    Metal {
+        albedo: [f64; 3],
+        #[serde(default)]
+        fuzz: f64,
+    },
+    Dielectric {
+        refraction_index: f64,
+    },
+    DiffuseLight {
+        emit: ColorSource,
+    },
+    //只从正面发光，出射方向偏离法线超过 outer_angle（半角）后不再发光
+    AreaSpotLight {
+        emit: ColorSource,
+        inner_angle: f64,
+        outer_angle: f64,
+    },
+    Isotropic {
+        albedo: ColorSource,
+    },
+    #[serde(alias = "principled")]
+    Disney(Box<DisneyDesc>),
+    //preset 和 eta/k 二选一
+    Conductor {
+        preset: Option<String>,
+        eta: Option<[f64; 3]>,
+        k: Option<[f64; 3]>,
+        #[serde(default)]
+        roughness: RoughnessDesc,
+    },
+    RoughDielectric {
+        refraction_index: f64,
+        #[serde(default)]
+        roughness: RoughnessDesc,
+    },
+    //给另一个材质加上纹理控制的镂空，纹理没有 alpha 时取 r 通道作为不透明度
+    AlphaMask {
+        material: String,
+        alpha: String,
+    },
+}
+
+//一个数为各向同性，两个数分别为切线和副切线方向的粗糙度
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(untagged)]
+enum RoughnessDesc {
+    Isotropic(f64),
+    Anisotropic([f64; 2]),
+}
+
+impl Default for RoughnessDesc {
+    fn default() -> Self {
+        RoughnessDesc::Isotropic(0.0)
+    }
+}
+
+impl RoughnessDesc {
+    fn validate(&self, field: &str) -> Result<(f64, f64), SceneError> {
+        let (u, v) = match *self {
+            RoughnessDesc::Isotropic(r) => (r, r),
+            RoughnessDesc::Anisotropic([u, v]) => (u, v),
+        };
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return Err(invalid(field, "must be between 0 and 1"));
+        }
+        Ok((u, v))
+    }
+}
+
+//未给出的参数取 DisneyParams 的默认值
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DisneyDesc {
+    base_color: Option<ColorSource>,
+    metallic: Option<ScalarSource>,
+    roughness: Option<ScalarSource>,
+    specular: Option<ScalarSource>,
+    specular_tint: Option<ScalarSource>,
+    sheen: Option<ScalarSource>,
+    clearcoat: Option<ScalarSource>,
+    clearcoat_gloss: Option<ScalarSource>,
+    transmission: Option<ScalarSource>,
+    ior: Option<ScalarSource>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: [f64; 3],
+        center2: Option<[f64; 3]>,
+        radius: f64,
+        material: Option<String>,
+    },
+    Quad {
+        q: [f64; 3],
+        u: [f64; 3],
+        v: [f64; 3],
+        material: Option<String>,
+    },
+    Box {
+        a: [f64; 3],
+        b: [f64; 3],
+        material: Option<String>,
+    },
+    ConstantMedium {
+        boundary: Box<ObjectDesc>,
+        density: f64,
+        albedo: ColorSource,
+    },
+    Model {
+        obj: String,
+        mtl: String,
+        material: Option<String>, //给出时代替 MTL 中的材质
+        #[serde(default)]
+        rotate_y: f64,
+        #[serde(default)]
+        offset: [f64; 3],
+        #[serde(default = "default_one")]
+        scale: f64,
+        #[serde(default = "default_crease_angle")]
+        crease_angle: f64, //OBJ 没有法线时生成法线的折痕角（度）
+    },
+    Translate {
+        offset: [f64; 3],
+        object: Box<ObjectDesc>,
+    },
+    RotateY {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    ExcludeLight {
+        object: Box<ObjectDesc>,
+    },
+}
+
+//等距柱状投影的环境贴图，设置后代替 background
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentDesc {
+    file: String,
+    #[serde(default)]
+    rotation: f64,
+    #[serde(default = "default_one")]
+    intensity: f64,
+}
+
+//Preetham 解析天空，设置后代替 background
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SkyDesc {
+    #[serde(default = "default_turbidity")]
+    turbidity: f64,
+    sun_direction: [f64; 3],
+    #[serde(default = "default_ground_albedo")]
+    ground_albedo: [f64; 3],
+    #[serde(default = "default_sun_size")]
+    sun_size: f64,
+    #[serde(default = "default_one")]
+    intensity: f64,
+    #[serde(default = "default_one")]
+    sun_intensity: f64,
+}
+
+//没有几何形状的光源，只通过光源采样照亮场景
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum PunctualDesc {
+    Point {
+        position: [f64; 3],
+        intensity: [f64; 3],
+    },
+    Spot {
+        position: [f64; 3],
+        direction: [f64; 3],
+        intensity: [f64; 3],
+        inner_angle: f64,
+        outer_angle: f64,
+    },
+    Directional {
+        direction: [f64; 3],
+        irradiance: [f64; 3],
+        #[serde(default)]
+        angular_diameter: f64,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    camera: CameraConfig,
+    #[serde(default = "default_background")]
+    background: [f64; 3],
+    environment: Option<EnvironmentDesc>,
+    sky: Option<SkyDesc>,
+    #[serde(default)]
+    textures: HashMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    lights: Vec<ObjectDesc>,
+    #[serde(default)]
+    punctual_lights: Vec<PunctualDesc>,
+}
+
+pub struct Scene {
+    pub camera: CameraConfig,
+    pub background: Arc<dyn Environment>,
+    pub world: HittableList,
+    pub lights: HittableList,
+    pub punctual_lights: Vec<Arc<dyn PunctualLight>>,
+    pub hash: u64, //场景描述的哈希，见 checkpoint::scene_hash
+}
+
+impl Scene {
+    pub fn render(self, options: RenderOptions) -> Result<(), RenderError> {
+        let lights = self.collect_lights();
+        let camera = self.camera;
+        let mut raytracer = RayTracer::new(
+            (camera.aspect_ratio, camera.image_width),
+            (
+                to_vec3(camera.look_from),
+                to_vec3(camera.look_at),
+                to_vec3(camera.vup),
+                camera.v_fov,
+            ),
+            self.world,
+            camera.samples_per_pixel,
+            camera.max_depth,
+            (camera.defocus_angle, camera.focus_dist),
+            self.background,
+        );
+        raytracer.set_options(options);
+        raytracer.render(lights)
+    }
+
+    //world 中发光的图元自动成为光源；lights 里手动给出的对象只有发光时才参与采样，
+    //不发光的对象采样到也没有贡献
+    pub fn collect_lights(&self) -> Vec<Light> {
+        let mut emitters = Vec::new();
+        self.world.collect_emitters(&mut emitters);
+        let mut lights: Vec<Light> = emitters.into_iter().map(Light::from_emitter).collect();
+        for object in &self.lights.objects {
+            if object.power() > 0.0 {
+                lights.push(Light::from_emitter(object.clone()));
+            }
+        }
+        //无穷远处的光源按照亮整个场景的包围球估计功率
+        let bbox = self.world.bounding_box();
+        let diagonal = Vec3::new(bbox.x.size(), bbox.y.size(), bbox.z.size()).length();
+        let radius = if diagonal.is_finite() && diagonal > 0.0 {
+            diagonal / 2.0
+        } else {
+            1.0
+        };
+        for light in &self.punctual_lights {
+            lights.push(Light::punctual(light.clone(), radius));
+        }
+        if self.background.importance_sampled() {
+            lights.push(Light::environment(self.background.clone(), radius));
+        }
+        lights
+    }
+}
+
+pub fn to_vec3(e: [f64; 3]) -> Vec3 {
+    Vec3::new(e[0], e[1], e[2])
+}
+
+pub fn load_scene(path: &str) -> Result<Scene, SceneError> {
+    let text = std::fs::read_to_string(path).map_err(|source| SceneError::Io {
+        path: path.to_string(),
+        source,
+    })?;
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let file = match extension.as_deref() {
+        Some("json") => parse_json(path, &text)?,
+        Some("toml") => parse_toml(path, &text)?,
+        _ => return Err(SceneError::UnsupportedFormat(path.to_string())),
+    };
+    build_scene(file, scene_hash(&text))
+}
+
+fn parse_json(path: &str, text: &str) -> Result<SceneFile, SceneError> {
+    let deserializer = &mut serde_json::Deserializer::from_str(text);
+    serde_path_to_error::deserialize(deserializer).map_err(|e| SceneError::Parse {
+        path: path.to_string(),
+        field: e.path().to_string(),
+        message: e.inner().to_string(),
+    })
+}
+
+fn parse_toml(path: &str, text: &str) -> Result<SceneFile, SceneError> {
+    let deserializer = toml::Deserializer::new(text);
+    serde_path_to_error::deserialize(deserializer).map_err(|e| SceneError::Parse {
+        path: path.to_string(),
+        field: e.path().to_string(),
+        message: e.inner().message().to_string(),
+    })
+}
+
+fn build_scene(file: SceneFile, hash: u64) -> Result<Scene, SceneError> {
+    file.camera.validate()?;
+
+    let mut builder = SceneBuilder {
+        textures: &file.textures,
+        materials: &file.materials,
+        texture_cache: HashMap::new(),
+        material_cache: HashMap::new(),
+        resolving: Vec::new(),
+        resolving_materials: Vec::new(),
+    };
+
+    let mut objects = HittableList::default();
+    for (i, desc) in file.objects.iter().enumerate() {
+        objects.add(builder.object(desc, &format!("objects[{}]", i), true)?);
+    }
+
+    let mut lights = HittableList::default();
+    for (i, desc) in file.lights.iter().enumerate() {
+        lights.add(builder.object(desc, &format!("lights[{}]", i), false)?);
+    }
+
+    let mut world = HittableList::default();
+    if !objects.objects.is_empty() {
+        world.add(Arc::new(BvhNode::from_list(&mut objects)));
+    }
+
+    let background: Arc<dyn Environment> = match (&file.environment, &file.sky) {
+        (Some(_), Some(_)) => {
+            return Err(invalid("sky", "cannot be used together with 'environment'"));
+        }
+        (None, Some(desc)) => {
+            if !(1.7..=10.0).contains(&desc.turbidity) {
+                return Err(invalid("sky.turbidity", "must be between 1.7 and 10"));
+            }
+            if to_vec3(desc.sun_direction).near_zero() {
+                return Err(invalid("sky.sun_direction", "must not be zero"));
+            }
+            if [desc.intensity, desc.sun_intensity, desc.sun_size]
+                .iter()
+                .any(|x| !x.is_finite() || *x < 0.0)
+            {
+                return Err(invalid(
+                    "sky",
+                    "intensities and sun_size must be finite and non-negative",
+                ));
+            }
+            Arc::new(PreethamSky::new(&SkyOptions {
+                turbidity: desc.turbidity,
+                sun_direction: to_vec3(desc.sun_direction),
+                ground_albedo: to_vec3(desc.ground_albedo),
+                sun_angular_size: desc.sun_size,
+                intensity: desc.intensity,
+                sun_intensity: desc.sun_intensity,
+            }))
+        }
+        (Some(desc), None) => {
+            if !desc.intensity.is_finite() || desc.intensity < 0.0 {
+                return Err(invalid(
+                    "environment.intensity",
+                    "must be a finite non-negative number",
+                ));
+            }
+            //和图片纹理一样，找不到时到 assets 目录下找
+            let path = if Path::new(&desc.file).is_file() {
+                PathBuf::from(&desc.file)
+            } else {
+                Path::new("assets").join(&desc.file)
+            };
+            let map = EnvironmentMap::load(&path, desc.rotation, desc.intensity)
+                .map_err(|e| invalid("environment.file", e.to_string()))?;
+            Arc::new(map)
+        }
+        (None, None) => Arc::new(ConstantBackground::new(to_vec3(file.background))),
+    };
+
+    let punctual_lights = file
+        .punctual_lights
+        .iter()
+        .enumerate()
+        .map(|(i, desc)| punctual_light(desc, &format!("punctual_lights[{}]", i)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Scene {
+        camera: file.camera,
+        background,
+        world,
+        lights,
+        punctual_lights,
+        hash,
+    })
+}
+
+fn punctual_light(desc: &PunctualDesc, field: &str) -> Result<Arc<dyn PunctualLight>, SceneError> {
+    let non_negative = |color: [f64; 3], name: &str| {
+        if color.iter().any(|c| !c.is_finite() || *c < 0.0) {
+            Err(invalid(
+                format!("{}.{}", field, name),
+                "must be a finite non-negative number",
+            ))
+        } else {
+            Ok(to_vec3(color))
+        }
+    };
+    let direction = |d: [f64; 3]| {
+        if to_vec3(d).near_zero() {
+            Err(invalid(format!("{}.direction", field), "must not be zero"))
+        } else {
+            Ok(to_vec3(d))
+        }
+    };
+    Ok(match desc {
+        PunctualDesc::Point {
+            position,
+            intensity,
+        } => Arc::new(PointLight::new(
+            to_vec3(*position),
+            non_negative(*intensity, "intensity")?,
+        )),
+        PunctualDesc::Spot {
+            position,
+            direction: d,
+            intensity,
+            inner_angle,
+            outer_angle,
+        } => {
+            if !(0.0..=180.0).contains(outer_angle) {
+                return Err(invalid(
+                    format!("{}.outer_angle", field),
+                    "must be between 0 and 180",
+                ));
+            }
+            if !(0.0..=*outer_angle).contains(inner_angle) {
+                return Err(invalid(
+                    format!("{}.inner_angle", field),
+                    "must be between 0 and outer_angle",
+                ));
+            }
+            Arc::new(SpotLight::new(
+                to_vec3(*position),
+                direction(*d)?,
+                non_negative(*intensity, "intensity")?,
+                *inner_angle,
+                *outer_angle,
+            ))
+        }
+        PunctualDesc::Directional {
+            direction: d,
+            irradiance,
+            angular_diameter,
+        } => {
+            if !(0.0..=180.0).contains(angular_diameter) {
+                return Err(invalid(
+                    format!("{}.angular_diameter", field),
+                    "must be between 0 and 180",
+                ));
+            }
+            Arc::new(DirectionalLight::new(
+                direction(*d)?,
+                non_negative(*irradiance, "irradiance")?,
+                *angular_diameter,
+            ))
+        }
+    })
+}
+
+struct SceneBuilder<'a> {
+    textures: &'a HashMap<String, TextureDesc>,
+    materials: &'a HashMap<String, MaterialDesc>,
+    texture_cache: HashMap<String, Arc<dyn Texture>>,
+    material_cache: HashMap<String, Arc<dyn Material>>,
+    resolving: Vec<String>, //正在构造的纹理，用于检测循环引用
+    resolving_materials: Vec<String>,
+}
+
+impl SceneBuilder<'_> {
+    fn color_source(
+        &mut self,
+        source: &ColorSource,
+        field: &str,
+    ) -> Result<Arc<dyn Texture>, SceneError> {
+        match source {
+            ColorSource::Color(c) => Ok(Arc::new(SolidColor::new(to_vec3(*c)))),
+            ColorSource::Texture(name) => self.texture(name, field),
+        }
+    }
+
+    fn scalar_source(
+        &mut self,
+        source: &Option<ScalarSource>,
+        field: &str,
+        range: (f64, f64),
+        default: &Arc<dyn Texture>,
+    ) -> Result<Arc<dyn Texture>, SceneError> {
+        match source {
+            Some(ScalarSource::Value(x)) => {
+                if !x.is_finite() || !(range.0..=range.1).contains(x) {
+                    let message = match range {
+                        (0.0, f64::INFINITY) => "must be a finite non-negative number".to_string(),
+                        (_, f64::INFINITY) => "must be a finite positive number".to_string(),
+                        (min, max) => format!("must be between {} and {}", min, max),
+                    };
+                    return Err(invalid(field, message));
+                }
+                Ok(Arc::new(SolidColor::new(Vec3::new(*x, *x, *x))))
+            }
+            Some(ScalarSource::Texture(name)) => self.texture(name, field),
+            None => Ok(default.clone()),
+        }
+    }
+
+    fn texture(&mut self, name: &str, field: &str) -> Result<Arc<dyn Texture>, SceneError> {
+        if let Some(tex) = self.texture_cache.get(name) {
+            return Ok(tex.clone());
+        }
+        let desc = self
+            .textures
+            .get(name)
+            .ok_or_else(|| invalid(field, format!("unknown texture '{}'", name)))?;
+        if self.resolving.iter().any(|n| n == name) {
+            return Err(invalid(
+                field,
+                format!("texture '{}' references itself", name),
+            ));
+        }
+        self.resolving.push(name.to_string());
+
+        let prefix = format!("textures.{}", name);
+        let tex: Arc<dyn Texture> = match desc {
+            TextureDesc::Solid { color } => Arc::new(SolidColor::new(to_vec3(*color))),
+            TextureDesc::Checker { scale, even, odd } => {
+                if !scale.is_finite() || *scale <= 0.0 {
+                    return Err(invalid(
+                        format!("{}.scale", prefix),
+                        "must be a finite positive number",
+                    ));
+                }
+                let even = self.color_source(even, &format!("{}.even", prefix))?;
+                let odd = self.color_source(odd, &format!("{}.odd", prefix))?;
+                Arc::new(CheckerTexture::new(*scale, even, odd))
+            }
+            TextureDesc::Image { file } => Arc::new(ImageTexture::new(file)),
+            TextureDesc::Noise { scale } => Arc::new(NoiseTexture::new(*scale)),
+            TextureDesc::Mapped {
+                color,
+                normal,
+                alpha,
+            } => Arc::new(MappedTexture::new(
+                color,
+                normal.as_deref(),
+                alpha.as_deref(),
+            )),
+        };
+
+        self.resolving.pop();
+        self.texture_cache.insert(name.to_string(), tex.clone());
+        Ok(tex)
+    }
+
+    fn material(&mut self, name: &str, field: &str) -> Result<Arc<dyn Material>, SceneError> {
+        if let Some(mat) = self.material_cache.get(name) {
+            return Ok(mat.clone());
+        }
+        let desc = self
+            .materials
+            .get(name)
+            .ok_or_else(|| invalid(field, format!("unknown material '{}'", name)))?;
+        if self.resolving_materials.iter().any(|n| n == name) {
+            return Err(invalid(
+                field,
+                format!("material '{}' references itself", name),
+            ));
+        }
+        self.resolving_materials.push(name.to_string());
+
+        let prefix = format!("materials.{}", name);
+        let mat: Arc<dyn Material> = match desc {
+            MaterialDesc::Lambertian { albedo } => {
+                let tex = self.color_source(albedo, &format!("{}.albedo", prefix))?;
+                Arc::new(Lambertian::from_tex(tex))
+            }
+            MaterialDesc::Metal { albedo, fuzz } => {
+                if !fuzz.is_finite() || *fuzz < 0.0 {
+                    return Err(invalid(
+                        format!("{}.fuzz", prefix),
+                        "must be a finite non-negative number",
+                    ));
+                }
+                Arc::new(Metal::new(to_vec3(*albedo), *fuzz))
+            }
+            MaterialDesc::Dielectric { refraction_index } => {
+                if !refraction_index.is_finite() || *refraction_index <= 0.0 {
+                    return Err(invalid(
+                        format!("{}.refraction_index", prefix),
+                        "must be a finite positive number",
+                    ));
+                }
+                Arc::new(Dielectric::new(*refraction_index))
+            }
+            MaterialDesc::DiffuseLight { emit } => {
+                let tex = self.color_source(emit, &format!("{}.emit", prefix))?;
+                Arc::new(DiffuseLight::from_texture(tex))
+            }
+            MaterialDesc::AreaSpotLight {
+                emit,
+                inner_angle,
+                outer_angle,
+            } => {
+                if !(0.0..=90.0).contains(outer_angle) {
+                    return Err(invalid(
+                        format!("{}.outer_angle", prefix),
+                        "must be between 0 and 90",
+                    ));
+                }
+                if !(0.0..=*outer_angle).contains(inner_angle) {
+                    return Err(invalid(
+                        format!("{}.inner_angle", prefix),
+                        "must be between 0 and outer_angle",
+                    ));
+                }
+                let tex = self.color_source(emit, &format!("{}.emit", prefix))?;
+                Arc::new(AreaSpotLight::from_texture(tex, *inner_angle, *outer_angle))
+            }
+            MaterialDesc::Isotropic { albedo } => {
+                let tex = self.color_source(albedo, &format!("{}.albedo", prefix))?;
+                Arc::new(Isotropic::new_from_texture(tex))
+            }
+            MaterialDesc::Disney(desc) => {
+                let DisneyDesc {
+                    base_color,
+                    metallic,
+                    roughness,
+                    specular,
+                    specular_tint,
+                    sheen,
+                    clearcoat,
+                    clearcoat_gloss,
+                    transmission,
+                    ior,
+                } = desc.as_ref();
+                let d = DisneyParams::default();
+                let unit = (0.0, 1.0);
+                let base_color = match base_color {
+                    Some(c) => self.color_source(c, &format!("{}.base_color", prefix))?,
+                    None => d.base_color.clone(),
+                };
+                let mut scalar = |source: &Option<ScalarSource>,
+                                  name: &str,
+                                  range: (f64, f64),
+                                  default: &Arc<dyn Texture>| {
+                    self.scalar_source(source, &format!("{}.{}", prefix, name), range, default)
+                };
+                let params = DisneyParams {
+                    base_color,
+                    metallic: scalar(metallic, "metallic", unit, &d.metallic)?,
+                    roughness: scalar(roughness, "roughness", unit, &d.roughness)?,
+                    specular: scalar(specular, "specular", (0.0, f64::INFINITY), &d.specular)?,
+                    specular_tint: scalar(specular_tint, "specular_tint", unit, &d.specular_tint)?,
+                    sheen: scalar(sheen, "sheen", (0.0, f64::INFINITY), &d.sheen)?,
+                    clearcoat: scalar(clearcoat, "clearcoat", (0.0, f64::INFINITY), &d.clearcoat)?,
+                    clearcoat_gloss: scalar(
+                        clearcoat_gloss,
+                        "clearcoat_gloss",
+                        unit,
+                        &d.clearcoat_gloss,
+                    )?,
+                    transmission: scalar(transmission, "transmission", unit, &d.transmission)?,
+                    ior: scalar(ior, "ior", (f64::MIN_POSITIVE, f64::INFINITY), &d.ior)?,
+                };
+                Arc::new(Disney::new(params))
+            }
+            MaterialDesc::Conductor {
+                preset,
+                eta,
+                k,
+                roughness,
+            } => {
+                let roughness = roughness.validate(&format!("{}.roughness", prefix))?;
+                let (eta, k) = match (preset, eta, k) {
+                    (Some(name), None, None) => conductor_preset(name).ok_or_else(|| {
+                        invalid(
+                            format!("{}.preset", prefix),
+                            format!(
+                                "unknown conductor '{}' (expected gold, silver, copper, aluminum or iron)",
+                                name
+                            ),
+                        )
+                    })?,
+                    (None, Some(eta), Some(k)) => (to_vec3(*eta), to_vec3(*k)),
+                    _ => {
+                        return Err(invalid(
+                            prefix.as_str(),
+                            "needs either 'preset' or both 'eta' and 'k'",
+                        ));
+                    }
+                };
+                if (0..3).any(|i| !eta[i].is_finite() || eta[i] <= 0.0) {
+                    return Err(invalid(
+                        format!("{}.eta", prefix),
+                        "must be a finite positive number",
+                    ));
+                }
+                if (0..3).any(|i| !k[i].is_finite() || k[i] < 0.0) {
+                    return Err(invalid(
+                        format!("{}.k", prefix),
+                        "must be a finite non-negative number",
+                    ));
+                }
+                Arc::new(Conductor::new(eta, k, roughness))
+            }
+            MaterialDesc::RoughDielectric {
+                refraction_index,
+                roughness,
+            } => {
+                if !refraction_index.is_finite() || *refraction_index <= 0.0 {
+                    return Err(invalid(
+                        format!("{}.refraction_index", prefix),
+                        "must be a finite positive number",
+                    ));
+                }
+                let roughness = roughness.validate(&format!("{}.roughness", prefix))?;
+                Arc::new(RoughDielectric::new(*refraction_index, roughness))
+            }
+            MaterialDesc::AlphaMask { material, alpha } => {
+                let inner = self.material(material, &format!("{}.material", prefix))?;
+                let mask = self.texture(alpha, &format!("{}.alpha", prefix))?;
+                Arc::new(AlphaMask::new(inner, mask))
+            }
+        };
+
+        self.resolving_materials.pop();
+        self.material_cache.insert(name.to_string(), mat.clone());
+        Ok(mat)
+    }
+
+    //光源列表里的物体只用于采样，可以不写材质。镂空只对三角形网格生效，
+    //球面、四边形和长方体不接受 alpha_mask
+    fn object_material(
+        &mut self,
+        material: &Option<String>,
+        field: &str,
+        required: bool,
+    ) -> Result<Arc<dyn Material>, SceneError> {
+        let field = format!("{}.material", field);
+        match material {
+            Some(name) => {
+                if let Some(MaterialDesc::AlphaMask { .. }) = self.materials.get(name) {
+                    return Err(invalid(
+                        field,
+                        format!("alpha_mask material '{}' only works on models", name),
+                    ));
+                }
+                self.material(name, &field)
+            }
+            None if required => Err(invalid(field, "missing material")),
+            None => Ok(Arc::new(DummyMaterial)),
+        }
+    }
+
+    fn object(
+        &mut self,
+        desc: &ObjectDesc,
+        field: &str,
+        material_required: bool,
+    ) -> Result<Arc<dyn Hittable>, SceneError> {
+        let object: Arc<dyn Hittable> = match desc {
+            ObjectDesc::Sphere {
+                center,
+                center2,
+                radius,
+                material,
+            } => {
+                if !radius.is_finite() || *radius <= 0.0 {
+                    return Err(invalid(
+                        format!("{}.radius", field),
+                        "must be a finite positive number",
+                    ));
+                }
+                let mat = self.object_material(material, field, material_required)?;
+                match center2 {
+                    Some(center2) => Arc::new(Sphere::new_moving(
+                        to_vec3(*center),
+                        to_vec3(*center2),
+                        *radius,
+                        mat,
+                    )),
+                    None => Arc::new(Sphere::new(to_vec3(*center), *radius, mat)),
+                }
+            }
+            ObjectDesc::Quad { q, u, v, material } => {
+                if cross(&to_vec3(*u), &to_vec3(*v)).near_zero() {
+                    return Err(invalid(format!("{}.v", field), "must not be parallel to u"));
+                }
+                let mat = self.object_material(material, field, material_required)?;
+                Arc::new(Quad::new(to_vec3(*q), to_vec3(*u), to_vec3(*v), mat))
+            }
+            ObjectDesc::Box { a, b, material } => {
+                let mat = self.object_material(material, field, material_required)?;
+                make_box(to_vec3(*a), to_vec3(*b), mat)
+            }
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                albedo,
+            } => {
+                if !density.is_finite() || *density <= 0.0 {
+                    return Err(invalid(
+                        format!("{}.density", field),
+                        "must be a finite positive number",
+                    ));
+                }
+                let boundary = self.object(boundary, &format!("{}.boundary", field), false)?;
+                let tex = self.color_source(albedo, &format!("{}.albedo", field))?;
+                Arc::new(ConstantMedium::from_texture(boundary, *density, tex))
+            }
+            ObjectDesc::Model {
+                obj,
+                mtl,
+                material,
+                rotate_y,
+                offset,
+                scale,
+                crease_angle,
+            } => {
+                if !(0.0..=180.0).contains(crease_angle) {
+                    return Err(invalid(
+                        format!("{}.crease_angle", field),
+                        "must be between 0 and 180",
+                    ));
+                }
+                if !Path::new(obj).is_file() {
+                    return Err(invalid(
+                        format!("{}.obj", field),
+                        format!("file '{}' not found", obj),
+                    ));
+                }
+                let material = match material {
+                    Some(name) => Some(self.material(name, &format!("{}.material", field))?),
+                    None => None,
+                };
+                load_model(
+                    obj,
+                    mtl,
+                    *rotate_y,
+                    to_vec3(*offset),
+                    *scale,
+                    material,
+                    *crease_angle,
+                )
+                .map_err(|e| invalid(field, e.to_string()))?
+            }
+            ObjectDesc::Translate { offset, object } => {
+                let inner = self.object(object, &format!("{}.object", field), material_required)?;
+                Arc::new(Translate::new(inner, to_vec3(*offset)))
+            }
+            ObjectDesc::RotateY { angle, object } => {
+                let inner = self.object(object, &format!("{}.object", field), material_required)?;
+                Arc::new(RotateY::new(inner, *angle))
+            }
+            ObjectDesc::ExcludeLight { object } => {
+                let inner = self.object(object, &format!("{}.object", field), material_required)?;
+                Arc::new(ExcludeLight::new(inner))
+            }
+        };
+        Ok(object)
+    }
+}
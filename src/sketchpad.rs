@@ -1,23 +1,136 @@
+use crate::tonemap::ToneMapping;
 use crate::vec3color::Color;
 use console::style;
-use image::{ImageBuffer, RgbImage};
+use image::codecs::bmp::BmpEncoder;
+use image::codecs::hdr::HdrEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::openexr::OpenExrEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::pnm::{PnmEncoder, PnmSubtype, SampleEncoding};
+use image::codecs::tiff::TiffEncoder;
+use image::{
+    DynamicImage, ExtendedColorType, ImageBuffer, ImageEncoder, ImageError, Rgb, Rgb32FImage,
+    RgbImage,
+};
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg { quality: u8 },
+    Ppm,
+    Bmp,
+    Tiff,
+    Exr, //线性浮点 OpenEXR
+    Hdr, //线性浮点 Radiance RGBE
+}
+
+impl ImageFormat {
+    pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        Self::from_name(&extension)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg {
+                quality: Self::DEFAULT_JPEG_QUALITY,
+            }),
+            "ppm" => Some(ImageFormat::Ppm),
+            "bmp" => Some(ImageFormat::Bmp),
+            "tif" | "tiff" => Some(ImageFormat::Tiff),
+            "exr" => Some(ImageFormat::Exr),
+            "hdr" => Some(ImageFormat::Hdr),
+            _ => None,
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, ImageFormat::Exr | ImageFormat::Hdr)
+    }
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Encode(ImageError),
+    UnknownFormat(String),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "cannot write the image file: {}", e),
+            SaveError::Encode(e) => write!(f, "cannot encode the image: {}", e),
+            SaveError::UnknownFormat(path) => write!(
+                f,
+                "cannot infer the image format of '{}', expected png, jpg, ppm, bmp, tiff, exr or hdr",
+                path
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SaveError::Io(e) => Some(e),
+            SaveError::Encode(e) => Some(e),
+            SaveError::UnknownFormat(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SaveError {
+    fn from(e: std::io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
+
+impl From<ImageError> for SaveError {
+    fn from(e: ImageError) -> Self {
+        SaveError::Encode(e)
+    }
+}
+
+//保存线性辐射度，写出 LDR 格式时才做 gamma 和量化
 pub struct Sketchpad {
-    image: RgbImage,
+    image: Rgb32FImage,
+    tone_mapping: ToneMapping,
 }
 
 impl Sketchpad {
     pub fn new(width: u32, aspect_ratio: f64) -> Self {
         let height = (width as f64 / aspect_ratio) as u32;
         let height = if height < 1 { 1 } else { height };
+        Self::with_size(width, height)
+    }
+
+    pub fn with_size(width: u32, height: u32) -> Self {
         Self {
             image: ImageBuffer::new(width, height),
+            tone_mapping: ToneMapping::default(),
         }
     }
 
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+    }
+
     pub fn draw(&mut self, x: u32, y: u32, color: Color) {
         let pixel = self.image.get_pixel_mut(x, y);
-        *pixel = image::Rgb(Color::write_color(&color));
+        *pixel = Rgb([color.x() as f32, color.y() as f32, color.z() as f32]);
+    }
+
+    pub fn color(&self, x: u32, y: u32) -> Color {
+        let pixel = self.image.get_pixel(x, y);
+        Color::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64)
     }
 
     pub fn width(&self) -> u32 {
@@ -28,16 +141,72 @@ impl Sketchpad {
         self.image.height()
     }
 
-    pub fn save(&self) {
-        let path = std::path::Path::new("output/final_scene.png");
-        let prefix = path.parent().unwrap();
-        std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
-        println!(
-            "Output image as \"{}\"",
-            style(path.to_str().unwrap()).yellow()
-        );
-        self.image
-            .save(path)
-            .expect("Cannot save the image to the file");
+    fn to_ldr(&self) -> RgbImage {
+        ImageBuffer::from_fn(self.width(), self.height(), |x, y| {
+            Rgb(self.tone_mapping.apply(&self.color(x, y)))
+        })
+    }
+
+    //NaN、无穷大和负值写进 HDR 文件会污染后期合成，统一置零
+    fn to_hdr(&self) -> Rgb32FImage {
+        let mut image = self.image.clone();
+        for value in image.iter_mut() {
+            if !value.is_finite() || *value < 0.0 {
+                *value = 0.0;
+            }
+        }
+        image
+    }
+
+    //format 为 None 时根据扩展名推断格式
+    pub fn save(&self, path: &Path, format: Option<ImageFormat>) -> Result<(), SaveError> {
+        let format = match format.or_else(|| ImageFormat::from_path(path)) {
+            Some(format) => format,
+            None => return Err(SaveError::UnknownFormat(path.display().to_string())),
+        };
+        if let Some(prefix) = path.parent() {
+            std::fs::create_dir_all(prefix)?;
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        if format.is_hdr() {
+            let image = self.to_hdr();
+            match format {
+                ImageFormat::Exr => DynamicImage::ImageRgb32F(image)
+                    .write_with_encoder(OpenExrEncoder::new(&mut writer))?,
+                _ => {
+                    let pixels: Vec<Rgb<f32>> = image.pixels().copied().collect();
+                    HdrEncoder::new(&mut writer).encode(
+                        &pixels,
+                        self.width() as usize,
+                        self.height() as usize,
+                    )?
+                }
+            }
+        } else {
+            let image = self.to_ldr();
+            let (data, width, height) = (image.as_raw(), self.width(), self.height());
+            let color_type = ExtendedColorType::Rgb8;
+            match format {
+                ImageFormat::Jpeg { quality } => {
+                    JpegEncoder::new_with_quality(&mut writer, quality.clamp(1, 100))
+                        .write_image(data, width, height, color_type)?
+                }
+                ImageFormat::Ppm => PnmEncoder::new(&mut writer)
+                    .with_subtype(PnmSubtype::Pixmap(SampleEncoding::Binary))
+                    .write_image(data, width, height, color_type)?,
+                ImageFormat::Bmp => {
+                    BmpEncoder::new(&mut writer).write_image(data, width, height, color_type)?
+                }
+                ImageFormat::Tiff => {
+                    TiffEncoder::new(&mut writer).write_image(data, width, height, color_type)?
+                }
+                _ => PngEncoder::new(&mut writer).write_image(data, width, height, color_type)?,
+            }
+        }
+        std::io::Write::flush(&mut writer)?;
+
+        println!("Output image as \"{}\"", style(path.display()).yellow());
+        Ok(())
     }
 }
@@ -0,0 +1,253 @@
+use crate::environment::Environment;
+use crate::onb::ONB;
+use crate::random::random_2d;
+use crate::vec3::{Vec3, dot, unit_vector};
+use crate::vec3color::Color;
+use std::f64::consts::PI;
+
+//Preetham 模型给出的亮度单位为 kcd/m²，缩放到和场景中其他光源相近的量级，
+//正午阳光下白色漫反射面的亮度约为 1
+const RADIANCE_SCALE: f64 = 0.04;
+//大气层外的太阳亮度 (kcd/m²)
+const SUN_LUMINANCE: f64 = 1.6e6;
+//R、G、B 取的代表波长 (μm)
+const WAVELENGTHS: [f64; 3] = [0.68, 0.55, 0.44];
+
+pub struct SkyOptions {
+    pub turbidity: f64,        //大气浑浊度，2 为晴朗，10 为雾霾
+    pub sun_direction: Vec3,   //指向太阳
+    pub ground_albedo: Color,  //地平线以下地面的反照率
+    pub sun_angular_size: f64, //太阳的视直径（度）
+    pub intensity: f64,
+    pub sun_intensity: f64,
+}
+
+impl Default for SkyOptions {
+    fn default() -> Self {
+        Self {
+            turbidity: 3.0,
+            sun_direction: Vec3::new(0.0, 1.0, 1.0),
+            ground_albedo: Color::new(0.3, 0.3, 0.3),
+            sun_angular_size: 0.53,
+            intensity: 1.0,
+            sun_intensity: 1.0,
+        }
+    }
+}
+
+//Perez 分布函数 F(θ, γ) 的五个系数
+struct Perez([f64; 5]);
+
+impl Perez {
+    fn new(turbidity: f64, coefficients: [(f64, f64); 5]) -> Self {
+        Self(coefficients.map(|(a, b)| a * turbidity + b))
+    }
+
+    fn value(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.0;
+        let cos_gamma = gamma.cos();
+        (1.0 + a * (b / cos_theta.max(1e-3)).exp())
+            * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+//Preetham 解析天空加上一个太阳圆盘。太阳按方向光处理，在它的视锥内均匀采样；
+//天空本身亮度变化平缓，只靠材质采样
+pub struct PreethamSky {
+    sun: Vec3,
+    sun_cos_max: f64,
+    sun_radiance: Color,
+    zenith: [f64; 3], //天顶的 Y、x、y
+    perez: [Perez; 3],
+    theta_sun: f64,
+    intensity: f64,
+    ground: Color,
+    average: Color,
+}
+
+impl PreethamSky {
+    pub fn new(options: &SkyOptions) -> Self {
+        let t = options.turbidity.clamp(1.7, 10.0);
+        let sun = unit_vector(&options.sun_direction);
+        //模型只在太阳位于地平线以上时有效
+        let theta_sun = sun.y().clamp(-1.0, 1.0).acos().min(PI / 2.0 - 1e-3);
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (s1, s2, s3) = (theta_sun, theta_sun.powi(2), theta_sun.powi(3));
+        let zenith_x = t * t * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s1)
+            + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s1 + 0.00394)
+            + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s1 + 0.25886);
+        let zenith_y = t * t * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s1)
+            + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s1 + 0.00516)
+            + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s1 + 0.26688);
+
+        let perez = [
+            Perez::new(
+                t,
+                [
+                    (0.1787, -1.4630),
+                    (-0.3554, 0.4275),
+                    (-0.0227, 5.3251),
+                    (0.1206, -2.5771),
+                    (-0.0670, 0.3703),
+                ],
+            ),
+            Perez::new(
+                t,
+                [
+                    (-0.0193, -0.2592),
+                    (-0.0665, 0.0008),
+                    (-0.0004, 0.2125),
+                    (-0.0641, -0.8989),
+                    (-0.0033, 0.0452),
+                ],
+            ),
+            Perez::new(
+                t,
+                [
+                    (-0.0167, -0.2608),
+                    (-0.0950, 0.0092),
+                    (-0.0079, 0.2102),
+                    (-0.0441, -1.6537),
+                    (-0.0109, 0.0529),
+                ],
+            ),
+        ];
+
+        let sun_radius = (options.sun_angular_size / 2.0).to_radians().max(1e-4);
+        let sun_radiance = if sun.y() > 0.0 {
+            sun_transmittance(t, theta_sun)
+                * (SUN_LUMINANCE * RADIANCE_SCALE * options.sun_intensity)
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        };
+
+        let mut sky = Self {
+            sun,
+            sun_cos_max: sun_radius.cos(),
+            sun_radiance,
+            zenith: [zenith_luminance, zenith_x, zenith_y],
+            perez,
+            theta_sun,
+            intensity: options.intensity,
+            ground: Color::new(0.0, 0.0, 0.0),
+            average: Color::new(0.0, 0.0, 0.0),
+        };
+
+        //数值积分出地面得到的照度，地平线以下按理想漫反射的地面处理
+        let (rows, columns) = (32, 64);
+        let mut irradiance = Color::new(0.0, 0.0, 0.0);
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        for j in 0..rows {
+            let theta = (j as f64 + 0.5) / rows as f64 * PI / 2.0;
+            let d_omega = theta.sin() * (PI / 2.0 / rows as f64) * (2.0 * PI / columns as f64);
+            for i in 0..columns {
+                let phi = (i as f64 + 0.5) / columns as f64 * 2.0 * PI;
+                let direction = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let radiance = sky.sky_radiance(direction);
+                irradiance += radiance * (theta.cos() * d_omega);
+                total += radiance * d_omega;
+            }
+        }
+        let sun_solid_angle = 2.0 * PI * (1.0 - sky.sun_cos_max);
+        irradiance += sky.sun_radiance * (sun.y().max(0.0) * sun_solid_angle);
+        total += sky.sun_radiance * sun_solid_angle;
+        sky.ground = options.ground_albedo * irradiance / PI;
+        sky.average = (total + sky.ground * (2.0 * PI)) / (4.0 * PI) * options.intensity;
+        sky
+    }
+
+    //不含太阳圆盘的天空
+    fn sky_radiance(&self, direction: Vec3) -> Color {
+        let cos_theta = direction.y();
+        let gamma = dot(&direction, &self.sun).clamp(-1.0, 1.0).acos();
+        let [yz, xz, yz_chroma] = self.zenith;
+        let big_y =
+            yz * self.perez[0].value(cos_theta, gamma) / self.perez[0].value(1.0, self.theta_sun);
+        let x =
+            xz * self.perez[1].value(cos_theta, gamma) / self.perez[1].value(1.0, self.theta_sun);
+        let y = yz_chroma * self.perez[2].value(cos_theta, gamma)
+            / self.perez[2].value(1.0, self.theta_sun);
+        xyy_to_rgb(x, y, big_y) * RADIANCE_SCALE
+    }
+}
+
+impl Environment for PreethamSky {
+    fn radiance(&self, direction: Vec3) -> Color {
+        let direction = unit_vector(&direction);
+        if direction.y() < 0.0 {
+            return self.ground * self.intensity;
+        }
+        let mut color = self.sky_radiance(direction);
+        if dot(&direction, &self.sun) >= self.sun_cos_max {
+            color += self.sun_radiance;
+        }
+        color * self.intensity
+    }
+
+    fn importance_sampled(&self) -> bool {
+        self.sun.y() > 0.0 && self.intensity > 0.0
+    }
+
+    //在太阳的视锥内均匀采样
+    fn random(&self) -> Vec3 {
+        let (r1, r2) = random_2d();
+        let cos_theta = 1.0 - r1 * (1.0 - self.sun_cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * r2;
+        ONB::new(&self.sun).transform(Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ))
+    }
+
+    fn pdf_value(&self, direction: Vec3) -> f64 {
+        if dot(&unit_vector(&direction), &self.sun) >= self.sun_cos_max {
+            1.0 / (2.0 * PI * (1.0 - self.sun_cos_max))
+        } else {
+            0.0
+        }
+    }
+
+    fn average_radiance(&self) -> Color {
+        self.average
+    }
+}
+
+//CIE xyY -> 线性 sRGB
+fn xyy_to_rgb(x: f64, y: f64, big_y: f64) -> Color {
+    if y <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    let big_x = x / y * big_y;
+    let big_z = (1.0 - x - y) / y * big_y;
+    Color::new(
+        (3.2406 * big_x - 1.5372 * big_y - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * big_y + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * big_y + 1.0570 * big_z).max(0.0),
+    )
+}
+
+//太阳光穿过大气后的透射率：Rayleigh 散射加上按浑浊度估计的气溶胶（Ångström 公式）
+fn sun_transmittance(turbidity: f64, theta_sun: f64) -> Color {
+    //Kasten-Young 相对大气质量
+    let zenith_degrees = theta_sun.to_degrees();
+    let air_mass = 1.0 / (theta_sun.cos() + 0.50572 * (96.07995 - zenith_degrees).powf(-1.6364));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let channel = |lambda: f64| {
+        let rayleigh = 0.008735 * lambda.powf(-4.08);
+        let aerosol = beta * lambda.powf(-1.3);
+        (-(rayleigh + aerosol) * air_mass).exp()
+    };
+    Color::new(
+        channel(WAVELENGTHS[0]),
+        channel(WAVELENGTHS[1]),
+        channel(WAVELENGTHS[2]),
+    )
+}
@@ -18,6 +18,7 @@ pub trait Texture: Send + Sync {
         None
     }
 
+    //不透明度，1 为完全不透明，None 表示纹理不带 alpha
     fn alpha(&self, _u: f64, _v: f64) -> Option<f64> {
         None
     }
@@ -45,13 +46,13 @@ impl Texture for SolidColor {
     }
 }
 
-pub struct CheckerTexture<T: Texture> {
+pub struct CheckerTexture<T: Texture + ?Sized> {
     inv_scale: f64,
     even: Arc<T>,
     odd: Arc<T>,
 }
 
-impl<T: Texture> CheckerTexture<T> {
+impl<T: Texture + ?Sized> CheckerTexture<T> {
     pub fn new(scale: f64, even: Arc<T>, odd: Arc<T>) -> Self {
         Self {
             inv_scale: 1.0 / scale,
@@ -69,7 +70,7 @@ impl CheckerTexture<SolidColor> {
     }
 }
 
-impl<T: Texture> Texture for CheckerTexture<T> {
+impl<T: Texture + ?Sized> Texture for CheckerTexture<T> {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
         let x = (self.inv_scale * p[0]).floor() as i32;
         let y = (self.inv_scale * p[1]).floor() as i32;
@@ -287,6 +288,7 @@ impl Texture for MappedTexture {
         let j = (v * map.height() as f64) as usize;
         let pixel = map.pixel_rgba(i, j);
 
-        Some(pixel[0] as f64 / 255.0)
+        //alpha 贴图的亮度开方后是被剔除的概率
+        Some(1.0 - (pixel[0] as f64 / 255.0).sqrt())
     }
 }
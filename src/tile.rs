@@ -0,0 +1,107 @@
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TileOrder {
+    Scanline,
+    Spiral, //从画面中心向外
+    Hilbert,
+}
+
+impl TileOrder {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None,
+        }
+    }
+}
+
+//像素范围 [x0, x1) x [y0, y1)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Tile {
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    pub fn pixel_count(&self) -> usize {
+        (self.width() * self.height()) as usize
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        (self.y0..self.y1).flat_map(move |y| (self.x0..self.x1).map(move |x| (x, y)))
+    }
+}
+
+pub fn make_tiles(width: u32, height: u32, tile_size: u32, order: TileOrder) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let tiles_x = width.div_ceil(tile_size);
+    let tiles_y = height.div_ceil(tile_size);
+
+    let mut coords: Vec<(u32, u32)> = (0..tiles_y)
+        .flat_map(|ty| (0..tiles_x).map(move |tx| (tx, ty)))
+        .collect();
+
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            //先按离中心的环数排序，同一环内按角度排序
+            let cx = (tiles_x as f64 - 1.0) / 2.0;
+            let cy = (tiles_y as f64 - 1.0) / 2.0;
+            let key = |&(tx, ty): &(u32, u32)| {
+                let dx = tx as f64 - cx;
+                let dy = ty as f64 - cy;
+                let ring = dx.abs().max(dy.abs()).round();
+                let angle = (dy.atan2(dx) + 2.0 * PI) % (2.0 * PI);
+                (ring, angle)
+            };
+            coords.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+        }
+        TileOrder::Hilbert => {
+            let n = tiles_x.max(tiles_y).next_power_of_two();
+            coords.sort_by_key(|&(tx, ty)| hilbert_index(n, tx, ty));
+        }
+    }
+
+    coords
+        .into_iter()
+        .map(|(tx, ty)| Tile {
+            x0: tx * tile_size,
+            y0: ty * tile_size,
+            x1: ((tx + 1) * tile_size).min(width),
+            y1: ((ty + 1) * tile_size).min(height),
+        })
+        .collect()
+}
+
+//(x, y) 在 n x n 希尔伯特曲线上的序号，n 为 2 的幂
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0u64;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u32::from(x & s > 0);
+        let ry = u32::from(y & s > 0);
+        d += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
@@ -0,0 +1,157 @@
+use crate::interval::Interval;
+use crate::vec3color::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMapper {
+    None, //只做曝光，超出部分直接截断
+    Reinhard,
+    ReinhardExtended { white: f64 }, //white 为映射到纯白的亮度
+    Aces,
+    Hable,
+}
+
+impl ToneMapper {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" | "clamp" | "exposure" => Some(ToneMapper::None),
+            "reinhard" => Some(ToneMapper::Reinhard),
+            "reinhard-extended" | "reinhard_extended" => {
+                Some(ToneMapper::ReinhardExtended { white: 4.0 })
+            }
+            "aces" => Some(ToneMapper::Aces),
+            "hable" | "uncharted2" => Some(ToneMapper::Hable),
+            _ => None,
+        }
+    }
+
+    pub fn map(&self, color: Color) -> Color {
+        match *self {
+            ToneMapper::None => color,
+            ToneMapper::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMapper::ReinhardExtended { white } => {
+                let white_sq = white * white;
+                scale_luminance(color, |l| l * (1.0 + l / white_sq) / (1.0 + l))
+            }
+            ToneMapper::Aces => map_channels(color, aces_filmic),
+            ToneMapper::Hable => {
+                //曝光偏移 2.0，线性白点 11.2，与 Uncharted 2 原始实现一致
+                let white_scale = 1.0 / hable_partial(11.2);
+                map_channels(color, |x| hable_partial(2.0 * x) * white_scale)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferCurve {
+    Gamma2, //开平方近似，和原先的输出保持一致
+    Srgb,
+}
+
+impl TransferCurve {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "gamma2" => Some(TransferCurve::Gamma2),
+            "srgb" => Some(TransferCurve::Srgb),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self, linear: f64) -> f64 {
+        if linear <= 0.0 {
+            return 0.0;
+        }
+        match self {
+            TransferCurve::Gamma2 => linear.sqrt(),
+            TransferCurve::Srgb => {
+                if linear <= 0.0031308 {
+                    12.92 * linear
+                } else {
+                    1.055 * linear.powf(1.0 / 2.4) - 0.055
+                }
+            }
+        }
+    }
+
+    //encode 的逆，用于读取 LDR 图片
+    pub fn decode(&self, encoded: f64) -> f64 {
+        if encoded <= 0.0 {
+            return 0.0;
+        }
+        match self {
+            TransferCurve::Gamma2 => encoded * encoded,
+            TransferCurve::Srgb => {
+                if encoded <= 0.04045 {
+                    encoded / 12.92
+                } else {
+                    ((encoded + 0.055) / 1.055).powf(2.4)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneMapper,
+    pub exposure: f64, //曝光补偿，单位为档 (EV)
+    pub transfer: TransferCurve,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: ToneMapper::None,
+            exposure: 0.0,
+            transfer: TransferCurve::Gamma2,
+        }
+    }
+}
+
+impl ToneMapping {
+    //线性辐射度 -> 曝光 -> 色调映射 -> 传递函数 -> 8 位量化
+    pub fn apply(&self, linear: &Color) -> [u8; 3] {
+        let sanitize = |c: f64| if c.is_nan() { 0.0 } else { c.max(0.0) };
+        let exposed = Color::new(
+            sanitize(linear.x()),
+            sanitize(linear.y()),
+            sanitize(linear.z()),
+        ) * 2f64.powf(self.exposure);
+        let mapped = self.operator.map(exposed);
+
+        let intensity = Interval::new(0.000, 0.999);
+        let quantize = |c: f64| (256.0 * intensity.clamp(self.transfer.encode(c))) as u8;
+        [
+            quantize(mapped.x()),
+            quantize(mapped.y()),
+            quantize(mapped.z()),
+        ]
+    }
+}
+
+pub fn luminance(color: &Color) -> f64 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
+fn scale_luminance(color: Color, curve: impl Fn(f64) -> f64) -> Color {
+    let l = luminance(&color);
+    if l <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    color * (curve(l) / l)
+}
+
+fn map_channels(color: Color, curve: impl Fn(f64) -> f64) -> Color {
+    Color::new(curve(color.x()), curve(color.y()), curve(color.z()))
+}
+
+//Krzysztof Narkowicz 的 ACES 拟合曲线
+fn aces_filmic(x: f64) -> f64 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    ((x * (a * x + b)) / (x * (c * x + d) + e)).clamp(0.0, 1.0)
+}
+
+fn hable_partial(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}